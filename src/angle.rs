@@ -0,0 +1,156 @@
+use std::{f64::consts::TAU, fmt};
+
+use bigdecimal::{BigDecimal, Zero};
+use num_bigint::BigInt;
+
+use crate::{builtins::Builtin, decimal, environment::Settings, error::CalcError, functions::CallContext, interpreter::Value, lexer::*, numeric};
+
+/// Unit of the angles taken by `sin`/`cos`/`tan` and returned by the inverse
+/// trig functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum AngleMode {
+    #[default]
+    Radians,
+    Degrees,
+    Gradians,
+}
+
+// Trig functions whose argument is an angle, and inverse trig functions whose result is one
+const ANGLE_ARGUMENTS: &[&str] = &["sin", "cos", "tan"];
+const ANGLE_RESULTS: &[&str] = &["asin", "acos", "atan", "atan2", "arg"];
+
+impl AngleMode {
+    pub const NAMES: &'static [&'static str] = &["rad", "deg", "grad"];
+
+    pub fn from_name(name: &str) -> Option<AngleMode> {
+        match name {
+            "rad" => Some(AngleMode::Radians),
+            "deg" => Some(AngleMode::Degrees),
+            "grad" => Some(AngleMode::Gradians),
+            _ => None,
+        }
+    }
+
+    fn full_turn(self) -> f64 {
+        match self {
+            AngleMode::Radians => TAU,
+            AngleMode::Degrees => 360f64,
+            AngleMode::Gradians => 400f64,
+        }
+    }
+}
+
+impl fmt::Display for AngleMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AngleMode::Radians => write!(f, "rad"),
+            AngleMode::Degrees => write!(f, "deg"),
+            AngleMode::Gradians => write!(f, "grad"),
+        }
+    }
+}
+
+// Hides the error converting to and from radians leaves behind, so cos(60) is 0.5 in degrees.
+// Decimals convert with pi at full precision, which leaves nothing to hide.
+fn round_noise(value: Value) -> Value {
+    match value {
+        Value::Number(value) if value.is_finite() => Value::Number(format!("{:.14e}", value).parse::<f64>().unwrap_or(value)),
+        value => value,
+    }
+}
+
+// sin, cos and tan of whole quarter turns are exact, and tan of an odd one is undefined
+fn quarter_turn(builtin: &Builtin, angle: f64, mode: AngleMode, span: Span) -> Option<Result<Value, CalcError>> {
+    let quarters: f64 = angle / (mode.full_turn() / 4f64);
+    if quarters.fract() != 0f64 {
+        return None;
+    }
+    let quarter: usize = quarters.rem_euclid(4f64) as usize;
+    let result: i32 = match builtin.name {
+        "sin" => [0, 1, 0, -1][quarter],
+        "cos" => [1, 0, -1, 0][quarter],
+        _ if quarter % 2 == 1 => return Some(Err(CalcError::domain(format!("tan is undefined at {} {}", angle, mode), span))),
+        _ => 0,
+    };
+    Some(Ok(Value::Integer(BigInt::from(result))))
+}
+
+/// Calls `builtin` with angles in `mode` rather than radians.
+pub fn call_builtin(builtin: &Builtin, args: &[Value], context: &CallContext, mode: AngleMode) -> Result<Value, CalcError> {
+    let span: Span = context.span;
+    let to_radians: Value = Value::Number(TAU / mode.full_turn());
+    if ANGLE_ARGUMENTS.contains(&builtin.name) {
+        let angle: Value = match (&args[0], numeric::to_float(&args[0])) {
+            (Value::Decimal(angle), _) => {
+                let (turn, quarter) = (BigDecimal::from(mode.full_turn() as u32), BigDecimal::from(mode.full_turn() as u32 / 4));
+                let angle: BigDecimal = angle % turn;
+                if (&angle % quarter).is_zero() {
+                    return quarter_turn(builtin, decimal::to_f64(&angle), mode, span).unwrap();
+                }
+                Value::Decimal(decimal::to_radians(&angle, mode.full_turn() as u32, context.settings.decimal_precision()))
+            }
+            // Reducing to a single turn first keeps large angles accurate
+            (Value::Number(_) | Value::Integer(_) | Value::Rational(_), Some(angle)) => {
+                if let Some(result) = quarter_turn(builtin, angle, mode, span) {
+                    return result;
+                }
+                Value::Number(angle.rem_euclid(mode.full_turn()) * (TAU / mode.full_turn()))
+            }
+            (angle, _) => numeric::binary_operation(&Operator::Multiply, angle.clone(), to_radians, span, context.settings)?,
+        };
+        return Ok(round_noise(builtin.call_radians(&[angle], context)?));
+    }
+    if ANGLE_RESULTS.contains(&builtin.name) {
+        let precision: u64 = context.settings.decimal_precision();
+        // Decimal results get a few extra digits, so asin(0.5) converts to exactly 30 degrees
+        let settings: Settings = Settings { precision: context.settings.precision.map(|precision| precision + 5), ..context.settings.clone() };
+        let result: Value = builtin.call_radians(args, &CallContext { span, settings: &settings })?;
+        if let Value::Decimal(result) = result {
+            return Ok(Value::Decimal(decimal::from_radians(&result, mode.full_turn() as u32, precision)));
+        }
+        let from_radians: Value = Value::Number(mode.full_turn() / TAU);
+        return Ok(round_noise(numeric::binary_operation(&Operator::Multiply, result, from_radians, span, context.settings)?));
+    }
+    builtin.call_radians(args, context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::Engine, error::ErrorKind};
+
+    fn eval_in(mode: AngleMode, precision: Option<u64>, input: &str) -> Result<String, ErrorKind> {
+        let mut engine: Engine = Engine::new();
+        engine.set_angle_mode(mode);
+        engine.set_precision(precision);
+        engine.eval(input).map(|value| value.to_string()).map_err(|error| error.kind)
+    }
+
+    #[test]
+    fn converts_angles() {
+        assert_eq!(eval_in(AngleMode::Degrees, None, "cos(60)"), Ok(String::from("0.5")));
+        assert_eq!(eval_in(AngleMode::Degrees, None, "asin(1)"), Ok(String::from("90")));
+        assert_eq!(eval_in(AngleMode::Gradians, None, "atan2(1, -1)"), Ok(String::from("150")));
+        assert_eq!(eval_in(AngleMode::Degrees, None, "sin(-270)"), Ok(String::from("1")));
+        assert_eq!(eval_in(AngleMode::Degrees, None, "tan(90)"), Err(ErrorKind::Domain));
+    }
+
+    #[test]
+    fn decimal_angles_keep_their_precision() {
+        let degree: &str = "0.01745240643728351281941897851631619247225";
+        assert_eq!(eval_in(AngleMode::Degrees, Some(40), "sin(1)"), Ok(String::from(degree)));
+        assert_eq!(eval_in(AngleMode::Degrees, Some(40), "sin(30)"), Ok(String::from("0.5")));
+        assert_eq!(eval_in(AngleMode::Degrees, Some(40), "asin(0.5)"), Ok(String::from("30")));
+        assert_eq!(eval_in(AngleMode::Gradians, Some(40), "acos(0)"), Ok(String::from("100")));
+        assert_eq!(eval_in(AngleMode::Degrees, Some(40), "sin(3600030)"), Ok(String::from("0.5")));
+    }
+
+    #[test]
+    fn arg_follows_the_angle_mode() {
+        assert_eq!(eval_in(AngleMode::Degrees, None, "arg(i)"), Ok(String::from("90")));
+        assert_eq!(eval_in(AngleMode::Gradians, None, "arg(-1 + 0i)"), Ok(String::from("200")));
+        assert_eq!(eval_in(AngleMode::Degrees, Some(30), "arg(-1)"), Ok(String::from("180")));
+        assert_eq!(eval_in(AngleMode::Radians, None, "arg(-1)"), Ok(std::f64::consts::PI.to_string()));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{error::CalcError, functions::Arity, lexer::Span, numeric};
+
+pub type FloatFunction = fn(&[f64], Span) -> Result<f64, CalcError>;
+
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub params: &'static str,
+    pub arity: Arity,
+    // Plain f64 version, `None` for builtins only defined on exact integers or lists
+    pub function: Option<FloatFunction>,
+}
+
+impl Builtin {
+    pub fn call_float(&self, args: &[f64], span: Span) -> Result<f64, CalcError> {
+        match self.function {
+            Some(function) => function(args, span),
+            // Aggregates and number theory builtins never get here, see Builtin::call
+            None => Err(CalcError::type_error(format!("{} expects a list or matrix argument", self.name), span)),
+        }
+    }
+}
+
+// Builtins that receive their arguments unevaluated, handled directly by the interpreter
+pub const SPECIAL_FORMS: &[(&str, &str)] = &[
+    ("deriv", "expr, var, at"),
+    ("diff", "expr, var"),
+    ("if", "cond, a, b"),
+    ("integrate", "expr, var, from, to"),
+    ("simplify", "expr"),
+    ("solve", "expr, var, guess"),
+    ("sumover", "expr, var, from, to"),
+];
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin { name: "abs", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].abs())) },
+    Builtin { name: "acos", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].acos())) },
+    Builtin { name: "acosh", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].acosh())) },
+    Builtin { name: "arg", params: "z", arity: Arity::Fixed(1), function: Some(|args, _| Ok(if args[0] < 0f64 { std::f64::consts::PI } else { 0f64 })) },
+    Builtin { name: "asin", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].asin())) },
+    Builtin { name: "asinh", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].asinh())) },
+    Builtin { name: "atan", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].atan())) },
+    Builtin { name: "atan2", params: "x, y", arity: Arity::Fixed(2), function: Some(|args, _| Ok(args[0].atan2(args[1]))) },
+    Builtin { name: "atanh", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].atanh())) },
+    Builtin { name: "cbrt", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].cbrt())) },
+    Builtin { name: "ceil", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].ceil())) },
+    Builtin { name: "conj", params: "z", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0])) },
+    Builtin { name: "cos", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].cos())) },
+    Builtin { name: "cosh", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].cosh())) },
+    Builtin { name: "count", params: "...", arity: Arity::Variadic(0), function: None },
+    Builtin { name: "cross", params: "a, b", arity: Arity::Fixed(2), function: None },
+    Builtin { name: "deg", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].to_degrees())) },
+    Builtin { name: "det", params: "matrix", arity: Arity::Fixed(1), function: None },
+    Builtin { name: "dfact", params: "n", arity: Arity::Fixed(1), function: None },
+    Builtin { name: "dot", params: "a, b", arity: Arity::Fixed(2), function: None },
+    Builtin { name: "exp", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].exp())) },
+    Builtin { name: "fact", params: "x", arity: Arity::Fixed(1), function: None },
+    Builtin { name: "factor", params: "n", arity: Arity::Fixed(1), function: None },
+    Builtin { name: "fib", params: "n", arity: Arity::Fixed(1), function: None },
+    Builtin { name: "floor", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].floor())) },
+    Builtin { name: "fract", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].fract())) },
+    Builtin { name: "gcd", params: "a, b", arity: Arity::Fixed(2), function: None },
+    Builtin { name: "hadamard", params: "a, b", arity: Arity::Fixed(2), function: None },
+    Builtin { name: "hypot", params: "x, y", arity: Arity::Fixed(2), function: Some(|args, _| Ok(args[0].hypot(args[1]))) },
+    Builtin { name: "im", params: "z", arity: Arity::Fixed(1), function: Some(|_, _| Ok(0f64)) },
+    Builtin { name: "inv", params: "matrix", arity: Arity::Fixed(1), function: None },
+    Builtin { name: "isprime", params: "n", arity: Arity::Fixed(1), function: None },
+    Builtin { name: "lcm", params: "a, b", arity: Arity::Fixed(2), function: None },
+    Builtin { name: "len", params: "list", arity: Arity::Fixed(1), function: None },
+    Builtin { name: "ln", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].ln())) },
+    Builtin { name: "log", params: "base, x", arity: Arity::Fixed(2), function: Some(|args, _| Ok(args[1].log(args[0]))) },
+    Builtin { name: "log10", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].log10())) },
+    Builtin { name: "log2", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].log2())) },
+    Builtin { name: "max", params: "...", arity: Arity::Variadic(1), function: None },
+    Builtin { name: "mean", params: "...", arity: Arity::Variadic(1), function: None },
+    Builtin { name: "median", params: "...", arity: Arity::Variadic(1), function: None },
+    Builtin { name: "min", params: "...", arity: Arity::Variadic(1), function: None },
+    Builtin { name: "mod_inv", params: "a, modulus", arity: Arity::Fixed(2), function: None },
+    Builtin { name: "mod_pow", params: "base, exponent, modulus", arity: Arity::Fixed(3), function: None },
+    Builtin { name: "mode", params: "...", arity: Arity::Variadic(1), function: None },
+    Builtin { name: "nCr", params: "n, k", arity: Arity::Fixed(2), function: None },
+    Builtin { name: "nextprime", params: "n", arity: Arity::Fixed(1), function: None },
+    Builtin { name: "nPr", params: "n, k", arity: Arity::Fixed(2), function: None },
+    Builtin { name: "percentile", params: "..., p", arity: Arity::Variadic(2), function: None },
+    Builtin { name: "pow", params: "x, exponent", arity: Arity::Fixed(2), function: Some(|args, span| numeric::float_power(args[0], args[1], span)) },
+    Builtin { name: "product", params: "...", arity: Arity::Variadic(0), function: None },
+    Builtin { name: "rad", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].to_radians())) },
+    Builtin { name: "re", params: "z", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0])) },
+    Builtin { name: "root", params: "root, x", arity: Arity::Fixed(2), function: Some(|args, _| Ok(args[1].powf(1f64/args[0]))) },
+    Builtin { name: "round", params: "x, digits?", arity: Arity::Range(1, 2), function: Some(|args, span| Ok(round(args[0], round_digits(args.get(1).copied().unwrap_or(0f64), span)?))) },
+    Builtin { name: "sign", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].signum())) },
+    Builtin { name: "sin", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].sin())) },
+    Builtin { name: "sinh", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].sinh())) },
+    Builtin { name: "sqrt", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].sqrt())) },
+    Builtin { name: "stdev", params: "...", arity: Arity::Variadic(1), function: None },
+    Builtin { name: "stdevp", params: "...", arity: Arity::Variadic(1), function: None },
+    Builtin { name: "sum", params: "...", arity: Arity::Variadic(0), function: None },
+    Builtin { name: "tan", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].tan())) },
+    Builtin { name: "tanh", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].tanh())) },
+    Builtin { name: "transpose", params: "matrix", arity: Arity::Fixed(1), function: None },
+    Builtin { name: "trunc", params: "x", arity: Arity::Fixed(1), function: Some(|args, _| Ok(args[0].trunc())) },
+    Builtin { name: "var", params: "...", arity: Arity::Variadic(1), function: None },
+    Builtin { name: "varp", params: "...", arity: Arity::Variadic(1), function: None },
+];
+
+pub fn is_special_form(name: &str) -> bool {
+    SPECIAL_FORMS.iter().any(|(special, _)| *special == name)
+}
+
+// Keeps 10^digits within the f64 range
+pub const MAX_ROUND_DIGITS: i32 = 300;
+
+/// Checks the digit count of `round(x, digits)`, which must be a whole
+/// number of at most [`MAX_ROUND_DIGITS`] either way.
+pub fn round_digits(digits: f64, span: Span) -> Result<i32, CalcError> {
+    if digits.fract() != 0f64 || digits.abs() > MAX_ROUND_DIGITS as f64 {
+        return Err(CalcError::domain(format!("round expects a whole number of digits between -{0} and {0}, got {1}", MAX_ROUND_DIGITS, digits), span));
+    }
+    Ok(digits as i32)
+}
+
+fn round(num: f64, digits: i32) -> f64 {
+    let scale: f64 = 10f64.powi(digits);
+    let result: f64 = (num * scale).round() / scale;
+    // Numbers too large to scale, like 1e300 to 100 digits, have no fractional digits to round anyway
+    if result.is_finite() { result } else { num }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::ErrorKind, testing::*};
+
+    #[test]
+    fn rounds_to_digits() {
+        assert_eq!(eval("round(2.5)"), "3");
+        assert_eq!(eval("round(pi, 2)"), "3.14");
+        assert_eq!(eval("round(1234.5, -2)"), "1200");
+        assert_eq!(eval("round(2.5, 300)"), "2.5");
+        assert_eq!(error_kind("round(2.5, 400)"), ErrorKind::Domain);
+        assert_eq!(error_kind("round(pi, 1.5)"), ErrorKind::Domain);
+    }
+
+    #[test]
+    fn checks_arity() {
+        assert_eq!(error_kind("atan2(1)"), ErrorKind::Arity);
+        assert_eq!(error_kind("round(1, 2, 3)"), ErrorKind::Arity);
+        assert_eq!(error_kind("nosuchfunction(1)"), ErrorKind::UnknownIdentifier);
+    }
+}
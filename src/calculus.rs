@@ -0,0 +1,305 @@
+use std::f64::consts::FRAC_PI_2;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use crate::{environment::*, error::CalcError, integer, interpreter::*, lexer::*, numeric, parser::*};
+
+// Special forms that evaluate an expression repeatedly with a variable bound to different values
+const FORMS: &[(&str, usize)] = &[("deriv", 3), ("integrate", 4), ("solve", 3), ("sumover", 4)];
+
+// Adaptive Simpson stops splitting once the estimated error is below this, or after MAX_DEPTH halvings
+const TOLERANCE: f64 = 1e-12;
+const MAX_DEPTH: usize = 20;
+// Integrands that need more evaluations than this, like sin(10^6*x), are reported as not converging
+const MAX_EVALUATIONS: usize = 100_000;
+// Tanh-sinh halves its step at most this many times, and settles for less accuracy since samples can't
+// get closer to a singular endpoint like 2 than the float spacing there
+const MAX_LEVELS: usize = 10;
+const SINGULAR_TOLERANCE: f64 = 1e-8;
+const NEWTON_ITERATIONS: usize = 100;
+const MAX_TERMS: u64 = 1_000_000;
+
+pub fn is_calculus_form(name: &str) -> bool {
+    FORMS.iter().any(|(form, _)| *form == name)
+}
+
+// An expression in one variable, evaluated by binding the variable and re-running evaluate_ast
+struct Bound<'a> {
+    name: &'a str,
+    expression: ASTNode,
+    variable: String,
+    env: &'a mut Environment,
+    evaluations: usize,
+}
+
+impl Bound<'_> {
+    fn value_at(&mut self, value: Value) -> Result<Value, CalcError> {
+        self.env.rebind(&self.variable, value);
+        evaluate_ast(self.expression.clone(), self.env)
+    }
+
+    fn at(&mut self, x: f64) -> Result<f64, CalcError> {
+        let span: Span = self.expression.span;
+        self.evaluations += 1;
+        if self.evaluations > MAX_EVALUATIONS {
+            return Err(CalcError::domain("integral does not converge", span));
+        }
+        match self.value_at(Value::Number(x))? {
+            value @ (Value::Number(_) | Value::Integer(_) | Value::Rational(_) | Value::Decimal(_)) => Ok(numeric::to_float(&value).unwrap()),
+            value => Err(CalcError::type_error(format!("{} needs an expression with a real result, got {} at {} = {}", self.name, value, self.variable, x), span)),
+        }
+    }
+
+    fn simpson(&mut self, a: f64, fa: f64, b: f64, fb: f64) -> Result<(f64, f64, f64), CalcError> {
+        let m: f64 = (a + b) / 2f64;
+        let fm: f64 = self.at(m)?;
+        Ok((m, fm, (b - a) / 6f64 * (fa + 4f64 * fm + fb)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn adaptive_simpson(&mut self, a: f64, fa: f64, b: f64, fb: f64, m: f64, fm: f64, whole: f64, tolerance: f64, depth: usize) -> Result<f64, CalcError> {
+        let (left_m, left_fm, left) = self.simpson(a, fa, m, fm)?;
+        let (right_m, right_fm, right) = self.simpson(m, fm, b, fb)?;
+        let delta: f64 = left + right - whole;
+        if depth == 0 || delta.abs() <= 15f64 * tolerance {
+            return Ok(left + right + delta / 15f64);
+        }
+        Ok(self.adaptive_simpson(a, fa, m, fm, left_m, left_fm, left, tolerance / 2f64, depth - 1)?
+            + self.adaptive_simpson(m, fm, b, fb, right_m, right_fm, right, tolerance / 2f64, depth - 1)?)
+    }
+
+    // Weighted tanh-sinh samples at t = start, start + step, ... on both sides, until they reach the endpoints
+    fn tanh_sinh_points(&mut self, a: f64, b: f64, start: f64, step: f64) -> Result<f64, CalcError> {
+        let half: f64 = (b - a) / 2f64;
+        let mut total: f64 = 0f64;
+        let mut t: f64 = start;
+        loop {
+            let u: f64 = FRAC_PI_2 * t.sinh();
+            let weight: f64 = FRAC_PI_2 * t.cosh() / (u.cosh() * u.cosh());
+            // Distance of the sample from each endpoint, computed directly so it doesn't round to the endpoint itself
+            let offset: f64 = half * 2f64 / (1f64 + (2f64 * u).exp());
+            if weight == 0f64 || (a + offset == a && b - offset == b) {
+                return Ok(total);
+            }
+            // A side whose samples have reached its endpoint stops contributing
+            if a + offset != a {
+                total += weight * self.at(a + offset)?;
+            }
+            if b - offset != b {
+                total += weight * self.at(b - offset)?;
+            }
+            t += step;
+        }
+    }
+
+    // Tanh-sinh quadrature never evaluates the endpoints, so it copes with singularities there like 1/sqrt(x) at 0
+    fn tanh_sinh(&mut self, a: f64, b: f64) -> Result<f64, CalcError> {
+        let half: f64 = (b - a) / 2f64;
+        let mut step: f64 = 1f64;
+        let mut sum: f64 = FRAC_PI_2 * self.at(a + half)? + self.tanh_sinh_points(a, b, 1f64, 1f64)?;
+        let mut estimate: f64 = half * step * sum;
+        for _ in 0..MAX_LEVELS {
+            step /= 2f64;
+            sum += self.tanh_sinh_points(a, b, step, 2f64 * step)?;
+            let next: f64 = half * step * sum;
+            if (next - estimate).abs() <= SINGULAR_TOLERANCE * next.abs().max(1f64) {
+                return Ok(next);
+            }
+            estimate = next;
+        }
+        Err(CalcError::domain("integral does not converge", self.expression.span))
+    }
+
+    fn integrate(&mut self, a: f64, b: f64) -> Result<f64, CalcError> {
+        let (fa, fb) = (self.at(a)?, self.at(b)?);
+        if !fa.is_finite() || !fb.is_finite() {
+            return self.tanh_sinh(a, b);
+        }
+        let (m, fm, whole) = self.simpson(a, fa, b, fb)?;
+        self.adaptive_simpson(a, fa, b, fb, m, fm, whole, TOLERANCE, MAX_DEPTH)
+    }
+
+    fn central_difference(&mut self, x: f64, h: f64) -> Result<f64, CalcError> {
+        Ok((self.at(x + h)? - self.at(x - h)?) / (2f64 * h))
+    }
+
+    // Ridders' method: central differences with shrinking steps, extrapolated to a step of zero
+    fn derivative(&mut self, x: f64) -> Result<f64, CalcError> {
+        const SHRINK: f64 = 1.4;
+        const STEPS: usize = 10;
+        let mut h: f64 = 0.1 * x.abs().max(1f64);
+        let mut table: Vec<Vec<f64>> = vec![vec![self.central_difference(x, h)?]];
+        let (mut best, mut error) = (table[0][0], f64::INFINITY);
+        for i in 1..STEPS {
+            h /= SHRINK;
+            let mut row: Vec<f64> = vec![self.central_difference(x, h)?];
+            let mut factor: f64 = SHRINK * SHRINK;
+            for j in 1..=i {
+                let extrapolated: f64 = (row[j - 1] * factor - table[i - 1][j - 1]) / (factor - 1f64);
+                factor *= SHRINK * SHRINK;
+                let estimate: f64 = (extrapolated - row[j - 1]).abs().max((extrapolated - table[i - 1][j - 1]).abs());
+                if estimate <= error {
+                    (best, error) = (extrapolated, estimate);
+                }
+                row.push(extrapolated);
+            }
+            // Stop once rounding error makes the extrapolation worse
+            let worse: bool = (row[i] - table[i - 1][i - 1]).abs() >= 2f64 * error;
+            table.push(row);
+            if worse {
+                break;
+            }
+        }
+        Ok(best)
+    }
+
+    fn solve(&mut self, guess: f64) -> Result<f64, CalcError> {
+        let mut x: f64 = guess;
+        for _ in 0..NEWTON_ITERATIONS {
+            let fx: f64 = self.at(x)?;
+            if fx == 0f64 {
+                return Ok(x);
+            }
+            let slope: f64 = self.derivative(x)?;
+            if slope == 0f64 || !slope.is_finite() {
+                break;
+            }
+            let next: f64 = x - fx / slope;
+            if (next - x).abs() <= f64::EPSILON * next.abs().max(1f64) {
+                return Ok(next);
+            }
+            x = next;
+        }
+        Err(CalcError::domain(format!("solve found no root near {} = {}", self.variable, guess), self.expression.span))
+    }
+}
+
+fn real(value: Value, name: &str, span: Span) -> Result<f64, CalcError> {
+    match numeric::to_float(&value) {
+        Some(value) if value.is_finite() => Ok(value),
+        _ => Err(CalcError::type_error(format!("{} needs finite real bounds, got {}", name, value), span)),
+    }
+}
+
+// `sumover(k^2, k, 1, 10)` adds exactly, so sums of integers and fractions stay exact
+fn sum_over(bound: &mut Bound, from: BigInt, to: BigInt, span: Span) -> Result<Value, CalcError> {
+    match (&to - &from).to_u64() {
+        Some(terms) if terms >= MAX_TERMS => return Err(CalcError::overflow(format!("sumover is limited to {} terms", MAX_TERMS), span)),
+        None if to >= from => return Err(CalcError::overflow(format!("sumover is limited to {} terms", MAX_TERMS), span)),
+        _ => (),
+    }
+    let mut total: Value = Value::Integer(BigInt::from(0));
+    let mut k: BigInt = from;
+    while k <= to {
+        let term: Value = bound.value_at(Value::Integer(k.clone()))?;
+        total = numeric::binary_operation(&Operator::Add, total, term, span, bound.env.settings())?;
+        k += 1;
+    }
+    Ok(total)
+}
+
+/// Evaluates `deriv`, `integrate`, `solve` or `sumover`, whose first
+/// argument is an expression in the variable named by the second.
+pub fn evaluate_form(name: &str, arg_nodes: Vec<ASTNode>, span: Span, env: &mut Environment) -> Result<Value, CalcError> {
+    let expected: usize = FORMS.iter().find(|(form, _)| *form == name).map(|(_, count)| *count).unwrap();
+    if arg_nodes.len() != expected {
+        return Err(CalcError::arity(format!("{} expects {} arguments, got {}", name, expected, arg_nodes.len()), span));
+    }
+    let mut arg_nodes = arg_nodes.into_iter();
+    let expression: ASTNode = arg_nodes.next().unwrap();
+    let variable_node: ASTNode = arg_nodes.next().unwrap();
+    let variable: String = match variable_node.kind {
+        ASTNodeKind::Variable(variable) => variable,
+        _ => return Err(CalcError::parse(format!("{} expects a variable name as its second argument", name), variable_node.span)),
+    };
+    // The remaining arguments are evaluated before the variable is bound
+    let mut args: Vec<Value> = Vec::new();
+    for arg_node in arg_nodes {
+        args.push(evaluate_ast(arg_node, env)?);
+    }
+    if !env.push_binding(&variable, Value::Number(0f64)) {
+        return Err(CalcError::overflow(format!("maximum call depth of {} exceeded in `{}`", MAX_CALL_DEPTH, name), span));
+    }
+    let mut bound: Bound = Bound { name, expression, variable, env, evaluations: 0 };
+    let mut args = args.into_iter();
+    let mut next_real = || real(args.next().unwrap(), name, span);
+    let result: Result<Value, CalcError> = match name {
+        "integrate" => match (next_real(), next_real()) {
+            (Ok(a), Ok(b)) => bound.integrate(a, b).and_then(|result| match result.is_finite() {
+                true => Ok(Value::Number(result)),
+                false => Err(CalcError::domain("integral does not converge", span)),
+            }),
+            (Err(error), _) | (_, Err(error)) => Err(error),
+        },
+        "deriv" => next_real().and_then(|x| bound.derivative(x)).map(Value::Number),
+        "solve" => next_real().and_then(|guess| bound.solve(guess)).map(Value::Number),
+        _ => {
+            let mut next_integer = || integer::to_integer(&args.next().unwrap(), name, span);
+            match (next_integer(), next_integer()) {
+                (Ok(from), Ok(to)) => sum_over(&mut bound, from, to, span),
+                (Err(error), _) | (_, Err(error)) => Err(error),
+            }
+        }
+    };
+    bound.env.pop_scope();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::Engine, error::ErrorKind, interpreter::Value, testing::*};
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= tolerance, "expected {} to be within {} of {}", actual, tolerance, expected);
+    }
+
+    #[test]
+    fn integrates() {
+        assert_close(eval_float("integrate(x^2, x, 0, 1)"), 1f64 / 3f64, 1e-12);
+        assert_close(eval_float("integrate(sin(t), t, 0, pi)"), 2f64, 1e-10);
+        assert_close(eval_float("integrate(exp(x), x, 1, 0)"), 1f64 - std::f64::consts::E, 1e-10);
+    }
+
+    #[test]
+    fn integrates_singular_endpoints() {
+        assert_close(eval_float("integrate(1/sqrt(x), x, 0, 1)"), 2f64, 1e-12);
+        assert_close(eval_float("integrate(ln(x), x, 0, 1)"), -1f64, 1e-12);
+        assert_close(eval_float("integrate(1/sqrt(1 - x^2), x, -1, 1)"), std::f64::consts::PI, 1e-7);
+        assert_eq!(error_kind("integrate(1/x, x, 0, 1)"), ErrorKind::Domain);
+    }
+
+    #[test]
+    fn gives_up_on_wild_integrands() {
+        assert_eq!(error_kind("integrate(sin(10^6*x), x, 0, 1)"), ErrorKind::Domain);
+    }
+
+    #[test]
+    fn differentiates_numerically() {
+        assert_close(eval_float("deriv(x^3, x, 2)"), 12f64, 1e-8);
+        assert_close(eval_float("deriv(sin(x), x, 0)"), 1f64, 1e-8);
+    }
+
+    #[test]
+    fn solves() {
+        assert_close(eval_float("solve(x^2 - 2, x, 1)"), 2f64.sqrt(), 1e-12);
+        assert_close(eval_float("solve(cos(x) - x, x, 1)"), 0.7390851332151607, 1e-12);
+        assert_eq!(error_kind("solve(x^2 + 1, x, 1)"), ErrorKind::Domain);
+    }
+
+    #[test]
+    fn sums_exactly() {
+        let mut engine: Engine = Engine::new();
+        assert_eq!(engine.eval("sumover(k^2, k, 1, 10)").unwrap().to_string(), "385");
+        assert!(matches!(engine.eval("sumover(1/k, k, 1, 3)").unwrap(), Value::Rational(_)));
+        assert_eq!(engine.eval("sumover(k, k, 1, 10^7)").unwrap_err().kind, ErrorKind::Overflow);
+    }
+
+    #[test]
+    fn variable_does_not_leak() {
+        let mut engine: Engine = Engine::new();
+        engine.eval("x = 5").unwrap();
+        engine.eval("integrate(x, x, 0, 1)").unwrap();
+        assert_eq!(engine.eval("x").unwrap().to_string(), "5");
+    }
+}
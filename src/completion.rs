@@ -0,0 +1,62 @@
+use std::borrow::Cow;
+
+use cli_calc::Engine;
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Context, Helper,
+};
+
+pub const COMMANDS: &[&str] = &[":help", ":opers", ":funcs", ":format", ":precision", ":angle", ":history", ":debug", ":exit"];
+
+// Tab completion for the REPL, plus the yellow prompt
+#[derive(Default)]
+pub struct CalcHelper {
+    names: Vec<String>,
+}
+
+impl CalcHelper {
+    // Refresh the candidates after every line so new variables and functions complete
+    pub fn update(&mut self, engine: &Engine) {
+        self.names = engine.names();
+    }
+}
+
+impl Completer for CalcHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let start: usize = line[..pos]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+            .last()
+            .map_or(pos, |(index, _)| index);
+        let word: &str = &line[start..pos];
+
+        // `:` commands only make sense at the start of the line
+        if line[..start].trim_start() == ":" {
+            let candidates: Vec<String> = COMMANDS.iter()
+                .filter(|command| command[1..].starts_with(word))
+                .map(|command| command[1..].to_string())
+                .collect();
+            return Ok((start, candidates));
+        }
+        if word.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+        Ok((start, self.names.iter().filter(|name| name.starts_with(word)).cloned().collect()))
+    }
+}
+
+impl Highlighter for CalcHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        Cow::Owned(format!("\x1b[38;5;11m{}\x1b[0m", prompt))
+    }
+}
+
+impl Hinter for CalcHelper {
+    type Hint = String;
+}
+
+impl Validator for CalcHelper {}
+
+impl Helper for CalcHelper {}
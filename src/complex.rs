@@ -0,0 +1,124 @@
+use num_complex::Complex64;
+
+use crate::{builtins::Builtin, error::CalcError, functions::CallContext, interpreter::Value, lexer::*, numeric};
+
+pub const IMAGINARY_UNIT: &str = "i";
+
+/// Results with no imaginary part go back to being plain numbers.
+pub fn normalize(value: Complex64) -> Value {
+    if value.im == 0f64 {
+        Value::Number(value.re)
+    } else {
+        Value::Complex(value)
+    }
+}
+
+pub fn to_complex(value: &Value) -> Option<Complex64> {
+    match value {
+        Value::Complex(value) => Some(*value),
+        value => numeric::to_float(value).map(|value| Complex64::new(value, 0f64)),
+    }
+}
+
+// `3 - 2i`, `0.5i`, `-i`
+pub fn to_string(value: &Complex64) -> String {
+    let imaginary = |im: f64| if im == 1f64 { String::from("i") } else { format!("{}i", im) };
+    if value.re == 0f64 {
+        return if value.im == -1f64 { String::from("-i") } else { imaginary(value.im) };
+    }
+    let sign: char = if value.im.is_sign_negative() { '-' } else { '+' };
+    format!("{} {} {}", value.re, sign, imaginary(value.im.abs()))
+}
+
+pub fn power(base: Complex64, exponent: Complex64) -> Complex64 {
+    // Repeated multiplication keeps `i^2` at exactly -1
+    if exponent.im == 0f64 && exponent.re.fract() == 0f64 && exponent.re.abs() <= i32::MAX as f64 {
+        return base.powi(exponent.re as i32);
+    }
+    base.powc(exponent)
+}
+
+pub fn operation(operator: &Operator, a: Complex64, b: Complex64, span: Span) -> Result<Value, CalcError> {
+    let result: Complex64 = match operator {
+        Operator::Add => a + b,
+        Operator::Subtract => a - b,
+        Operator::Multiply => a * b,
+        Operator::Divide if b == Complex64::new(0f64, 0f64) => return Err(CalcError::domain("division by zero", span)),
+        Operator::Divide => a / b,
+        Operator::Exponent => power(a, b),
+        _ => return Err(CalcError::type_error(format!("`{}` is not defined for complex numbers", operator), span)),
+    };
+    Ok(normalize(result))
+}
+
+// Complex versions of the builtins that have one
+fn complex_builtin(name: &str, args: &[Complex64]) -> Option<Value> {
+    let z: Complex64 = *args.first()?;
+    let result: Complex64 = match name {
+        "abs" => return Some(Value::Number(z.norm())),
+        "arg" => return Some(Value::Number(z.arg())),
+        "re" => return Some(Value::Number(z.re)),
+        "im" => return Some(Value::Number(z.im)),
+        "conj" => z.conj(),
+        "sqrt" => z.sqrt(),
+        "cbrt" => z.cbrt(),
+        "exp" => z.exp(),
+        "ln" => z.ln(),
+        "log" => args[1].ln() / z.ln(),
+        "log10" => z.log10(),
+        "log2" => z.log2(),
+        "pow" => power(z, args[1]),
+        "root" => power(args[1], z.inv()),
+        "sin" => z.sin(),
+        "cos" => z.cos(),
+        "tan" => z.tan(),
+        "asin" => z.asin(),
+        "acos" => z.acos(),
+        "atan" => z.atan(),
+        "sinh" => z.sinh(),
+        "cosh" => z.cosh(),
+        "tanh" => z.tanh(),
+        "asinh" => z.asinh(),
+        "acosh" => z.acosh(),
+        "atanh" => z.atanh(),
+        _ => return None,
+    };
+    Some(normalize(result))
+}
+
+/// Calls the complex version of `builtin`, failing with a type error when it
+/// only makes sense for real numbers.
+pub fn call_builtin(builtin: &Builtin, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+    let args: Vec<Complex64> = args.iter().map(|arg| {
+        to_complex(arg).ok_or_else(|| CalcError::type_error("Attempt to apply mathematical function on non-number", context.span))
+    }).collect::<Result<Vec<Complex64>, CalcError>>()?;
+    complex_builtin(builtin.name, &args)
+        .ok_or_else(|| CalcError::type_error(format!("`{}` is not defined for complex numbers", builtin.name), context.span))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::ErrorKind, testing::*};
+
+    #[test]
+    fn complex_arithmetic() {
+        assert_eq!(eval("(1+2i)*(3-4i)"), "11 + 2i");
+        assert_eq!(eval("(1+2i)/(3-4i)"), "-0.2 + 0.4i");
+        assert_eq!(eval("i^2"), "-1");
+        assert_eq!(eval("(1+i) == (1+i)"), "true");
+        assert_eq!(error_kind("(1+i)/0"), ErrorKind::Domain);
+        assert_eq!(error_kind("(1+i) < 2"), ErrorKind::Type);
+    }
+
+    #[test]
+    fn complex_builtins() {
+        assert_eq!(eval("sqrt(-4)"), "2i");
+        assert_eq!(eval("ln(-1)"), "3.141592653589793i");
+        assert_eq!(eval("abs(3+4i)"), "5");
+        assert_eq!(eval("re(3+4i) + im(3+4i)"), "7");
+        assert_eq!(eval("conj(3+4i)"), "3 - 4i");
+        assert_eq!(eval_float("arg(2i)"), std::f64::consts::FRAC_PI_2);
+        assert!(eval_float("abs(exp(i*pi) + 1)") < 1e-15);
+        assert_eq!(eval("sin(i)"), "1.1752011936438014i");
+    }
+}
@@ -0,0 +1,461 @@
+use std::{num::NonZeroU64, str::FromStr};
+
+use bigdecimal::{BigDecimal, Context, One, RoundingMode, Signed, ToPrimitive, Zero};
+use num_bigint::{BigInt, Sign};
+use num_rational::BigRational;
+use num_traits::Pow;
+
+use crate::{builtins::{round_digits, Builtin}, complex, error::CalcError, functions::CallContext, interpreter::Value, lexer::*};
+
+pub const DEFAULT_PRECISION: u64 = 50;
+pub const MAX_PRECISION: u64 = 1000;
+
+// Powers are capped at results of about 10^±MAX_EXPONENT, the decimal counterpart of `rational::MAX_BITS`
+const MAX_EXPONENT: f64 = 1e6;
+
+// Beyond these digits the constants are computed, see `constant`
+const STORED_DIGITS: u64 = 100;
+const CONSTANTS: &[(&str, &str)] = &[
+    ("pi", "3.14159265358979323846264338327950288419716939937510582097494459230781640628620899862803482534211706798214808651"),
+    ("e", "2.71828182845904523536028747135266249775724709369995957496696762772407663035354759457138217852516642742746639193"),
+    ("tau", "6.28318530717958647692528676655900576839433879875021164194988918461563281257241799725606965068423413596429617302"),
+    ("phi", "1.61803398874989484820458683436563811772030917980576286213544862270526046281890244970720720418939113748475408807"),
+];
+
+fn context(precision: u64) -> Context {
+    Context::default().with_precision(NonZeroU64::new(precision.max(1)).unwrap())
+}
+
+// A few guard digits keep intermediate steps from leaking rounding error into the result
+fn guarded_context(precision: u64) -> Context {
+    context(precision + 5)
+}
+
+pub fn round(value: BigDecimal, precision: u64) -> BigDecimal {
+    context(precision).round_decimal(value).normalized()
+}
+
+pub fn parse(text: &str, precision: u64, span: Span) -> Result<BigDecimal, CalcError> {
+    match BigDecimal::from_str(text) {
+        Ok(value) => Ok(round(value, precision)),
+        Err(_) => Err(CalcError::lex(format!("invalid number literal `{}`", text), span)),
+    }
+}
+
+/// Plain digits unless that would take more than a few padding zeros,
+/// then scientific notation like `1.5e-30`.
+pub fn to_string(value: &BigDecimal) -> String {
+    let value: BigDecimal = value.normalized();
+    if value.is_zero() || (value.order_of_magnitude() >= -7 && value.fractional_digit_count() >= -20) {
+        value.to_plain_string()
+    } else {
+        value.to_scientific_notation()
+    }
+}
+
+pub fn constant(name: &str, precision: u64) -> Option<BigDecimal> {
+    let (_, digits) = CONSTANTS.iter().find(|(constant, _)| *constant == name)?;
+    if precision <= STORED_DIGITS {
+        return Some(round(BigDecimal::from_str(digits).unwrap(), precision));
+    }
+    let ctx: Context = guarded_context(precision);
+    let value: BigDecimal = match name {
+        "pi" => pi(precision),
+        "tau" => pi(precision + 5) * BigDecimal::from(2),
+        "e" => BigDecimal::one().exp_with_context(&ctx),
+        _ => (BigDecimal::one() + BigDecimal::from(5).sqrt_with_context(&ctx).unwrap()).half(),
+    };
+    Some(round(value, precision))
+}
+
+// Goes through the shortest decimal representation, so 0.1 becomes exactly 0.1
+pub fn from_f64(value: f64, span: Span) -> Result<BigDecimal, CalcError> {
+    if !value.is_finite() {
+        return Err(CalcError::domain(format!("{} has no decimal representation", value), span));
+    }
+    Ok(BigDecimal::from_str(&value.to_string()).unwrap())
+}
+
+pub fn from_rational(value: &BigRational, precision: u64) -> BigDecimal {
+    let denominator: BigDecimal = BigDecimal::from(value.denom().clone());
+    round(BigDecimal::from(value.numer().clone()) * denominator.inverse_with_context(&guarded_context(precision)), precision)
+}
+
+pub fn to_rational(value: &BigDecimal) -> BigRational {
+    let (digits, scale) = value.as_bigint_and_exponent();
+    let ten: BigRational = BigRational::from_integer(BigInt::from(10));
+    BigRational::from_integer(digits) / ten.pow(scale as i32)
+}
+
+pub fn to_f64(value: &BigDecimal) -> f64 {
+    value.to_f64().unwrap_or(f64::NAN)
+}
+
+pub fn to_bigint(value: &BigDecimal) -> Option<BigInt> {
+    if value.is_integer() {
+        Some(value.with_scale(0).into_bigint_and_exponent().0)
+    } else {
+        None
+    }
+}
+
+pub fn operation(operator: &Operator, a: BigDecimal, b: BigDecimal, precision: u64, span: Span) -> Result<BigDecimal, CalcError> {
+    let result: BigDecimal = match operator {
+        Operator::Add => a + b,
+        Operator::Subtract => a - b,
+        Operator::Multiply => a * b,
+        Operator::Divide => {
+            if b.is_zero() {
+                return Err(CalcError::domain("division by zero", span));
+            }
+            a * b.inverse_with_context(&guarded_context(precision))
+        }
+        Operator::Modulo => {
+            if b.is_zero() {
+                return Err(CalcError::domain("division by zero", span));
+            }
+            a % b
+        }
+        Operator::Exponent => power(a, b, precision, span)?,
+        _ => return Err(CalcError::parse("Invalid binary operator", span)),
+    };
+    Ok(round(result, precision))
+}
+
+// Rough log10 of |x|, also for values far outside the f64 range
+fn magnitude(x: &BigDecimal) -> f64 {
+    let float: f64 = to_f64(x).abs();
+    if float.is_normal() { float.log10() } else { x.order_of_magnitude() as f64 }
+}
+
+// ln(x) for positive x: square roots bring x close to 1, where the series for 2 atanh((x - 1)/(x + 1)) converges quickly
+fn ln(x: &BigDecimal, precision: u64) -> BigDecimal {
+    let working: u64 = precision + 20;
+    let ctx: Context = context(working);
+    let threshold: BigDecimal = BigDecimal::new(BigInt::one(), 2);
+    let mut x: BigDecimal = x.clone();
+    let mut halvings: u32 = 0;
+    while (&x - BigDecimal::one()).abs() > threshold {
+        x = x.sqrt_with_context(&ctx).unwrap();
+        halvings += 1;
+    }
+    let z: BigDecimal = round((&x - BigDecimal::one()) * (&x + BigDecimal::one()).inverse_with_context(&ctx), working);
+    let z_squared: BigDecimal = round(z.square(), working);
+    let epsilon: BigDecimal = BigDecimal::new(BigInt::one(), working as i64);
+    let (mut sum, mut term, mut n) = (z.clone(), z, 1u64);
+    loop {
+        term = round(term * &z_squared, working);
+        n += 2;
+        let delta: BigDecimal = round(&term / BigDecimal::from(n), working);
+        if delta.abs() < epsilon {
+            break;
+        }
+        sum += delta;
+    }
+    round(sum * BigDecimal::from(BigInt::from(2).pow(halvings + 1)), precision)
+}
+
+// pi to `precision` digits, from Machin's formula once the stored digits run out
+pub fn pi(precision: u64) -> BigDecimal {
+    if precision <= STORED_DIGITS {
+        return constant("pi", precision).unwrap();
+    }
+    let working: u64 = precision + 10;
+    let fifth: BigDecimal = atan_series(&BigDecimal::new(BigInt::from(2), 1), working);
+    let two_hundred_thirty_ninth: BigDecimal = atan_series(&BigDecimal::from(239).inverse_with_context(&context(working)), working);
+    round(fifth * BigDecimal::from(16) - two_hundred_thirty_ninth * BigDecimal::from(4), precision)
+}
+
+/// `angle` in units of which `full_turn` make a turn, in radians with a few
+/// guard digits beyond `precision`.
+pub fn to_radians(angle: &BigDecimal, full_turn: u32, precision: u64) -> BigDecimal {
+    divide_by(&(angle * pi(precision + 5) * BigDecimal::from(2)), full_turn as u64, precision + 5)
+}
+
+/// `angle` in radians converted to units of which `full_turn` make a turn.
+pub fn from_radians(angle: &BigDecimal, full_turn: u32, precision: u64) -> BigDecimal {
+    let turn: BigDecimal = pi(precision + 5) * BigDecimal::from(2);
+    round(angle * BigDecimal::from(full_turn) * turn.inverse_with_context(&guarded_context(precision)), precision)
+}
+
+// x/n to `working` digits, cheaper than multiplying by the inverse of n
+fn divide_by(x: &BigDecimal, n: u64, working: u64) -> BigDecimal {
+    let (digits, scale) = x.as_bigint_and_exponent();
+    let padding: u64 = (working + 5).saturating_sub(x.digits());
+    round(BigDecimal::new(digits * BigInt::from(10).pow(padding as u32) / n, scale + padding as i64), working)
+}
+
+// z - z^3/3 + z^5/5 - ..., which converges quickly for small |z|
+fn atan_series(z: &BigDecimal, working: u64) -> BigDecimal {
+    let z_squared: BigDecimal = round(z.square(), working);
+    let epsilon: BigDecimal = BigDecimal::new(BigInt::one(), working as i64);
+    let (mut sum, mut power, mut n) = (z.clone(), z.clone(), 1u64);
+    loop {
+        power = round(-power * &z_squared, working);
+        n += 2;
+        let term: BigDecimal = divide_by(&power, n, working);
+        if term.abs() < epsilon {
+            return sum;
+        }
+        sum += term;
+    }
+}
+
+fn atan(x: &BigDecimal, precision: u64) -> BigDecimal {
+    let working: u64 = precision + 10;
+    let ctx: Context = context(working);
+    // atan(x) = ±pi/2 - atan(1/x) brings x into [-1, 1]
+    if x.abs() > BigDecimal::one() {
+        let half_pi: BigDecimal = pi(working).half();
+        let rest: BigDecimal = atan(&x.inverse_with_context(&ctx), working);
+        return round(if x.is_negative() { -half_pi - rest } else { half_pi - rest }, precision);
+    }
+    // atan(x) = 2 atan(x / (1 + sqrt(1 + x^2))) brings x close to 0
+    let threshold: BigDecimal = BigDecimal::new(BigInt::one(), 1);
+    let mut x: BigDecimal = x.clone();
+    let mut doublings: u32 = 0;
+    while x.abs() > threshold {
+        let root: BigDecimal = (BigDecimal::one() + x.square()).sqrt_with_context(&ctx).unwrap();
+        x = round(&x * (BigDecimal::one() + root).inverse_with_context(&ctx), working);
+        doublings += 1;
+    }
+    round(atan_series(&x, working) * BigDecimal::from(BigInt::from(2).pow(doublings)), precision)
+}
+
+// For |x| <= 1
+fn asin(x: &BigDecimal, precision: u64) -> BigDecimal {
+    let working: u64 = precision + 10;
+    if x.abs().is_one() {
+        let half_pi: BigDecimal = round(pi(working).half(), precision);
+        return if x.is_negative() { -half_pi } else { half_pi };
+    }
+    let root: BigDecimal = (BigDecimal::one() - x.square()).sqrt_with_context(&context(working)).unwrap();
+    atan(&(x * root.inverse_with_context(&context(working))), precision)
+}
+
+// Angle of the point (x, y), matching f64's y.atan2(x)
+fn atan2(y: &BigDecimal, x: &BigDecimal, precision: u64) -> BigDecimal {
+    let working: u64 = precision + 10;
+    if x.is_zero() {
+        let half_pi: BigDecimal = round(pi(working).half(), precision);
+        return match y.sign() {
+            Sign::Plus => half_pi,
+            Sign::Minus => -half_pi,
+            Sign::NoSign => BigDecimal::zero(),
+        };
+    }
+    let angle: BigDecimal = atan(&(y * x.inverse_with_context(&context(working))), working);
+    let turn: BigDecimal = match (x.is_negative(), y.is_negative()) {
+        (false, _) => BigDecimal::zero(),
+        (true, false) => pi(working),
+        (true, true) => -pi(working),
+    };
+    round(angle + turn, precision)
+}
+
+// Taylor series for both once x is reduced into [-pi, pi], `None` when x is too large for that to leave any digits
+fn sin_cos(x: &BigDecimal, precision: u64) -> Option<(BigDecimal, BigDecimal)> {
+    // Reducing cancels the leading digits of x, pi has to make up for them
+    let extra: f64 = magnitude(x).max(0f64).ceil();
+    if extra > MAX_PRECISION as f64 {
+        return None;
+    }
+    let reduction: u64 = precision + extra as u64 + 10;
+    let tau: BigDecimal = pi(reduction) * BigDecimal::from(2);
+    let turns: BigDecimal = (x * tau.inverse_with_context(&context(reduction))).with_scale_round(0, RoundingMode::HalfEven);
+    let working: u64 = precision + 10;
+    let x: BigDecimal = round(x - turns * tau, working);
+    let x_squared: BigDecimal = round(x.square(), working);
+    let epsilon: BigDecimal = BigDecimal::new(BigInt::one(), working as i64);
+    let (mut sin, mut cos) = (x.clone(), BigDecimal::one());
+    let (mut sin_term, mut cos_term) = (x, BigDecimal::one());
+    let mut n: u64 = 0;
+    while sin_term.abs() >= epsilon || cos_term.abs() >= epsilon {
+        cos_term = divide_by(&(-cos_term * &x_squared), (n + 1) * (n + 2), working);
+        sin_term = divide_by(&(-sin_term * &x_squared), (n + 2) * (n + 3), working);
+        sin += &sin_term;
+        cos += &cos_term;
+        n += 2;
+    }
+    Some((round(sin, precision), round(cos, precision)))
+}
+
+fn power(base: BigDecimal, exponent: BigDecimal, precision: u64, span: Span) -> Result<BigDecimal, CalcError> {
+    if exponent.is_zero() {
+        return Ok(BigDecimal::one());
+    }
+    if base.is_zero() {
+        return if exponent.is_negative() { Err(CalcError::domain("division by zero", span)) } else { Ok(base) };
+    }
+    let integer: Option<BigInt> = to_bigint(&exponent);
+    let negative: bool = match &integer {
+        Some(integer) => base.is_negative() && integer.bit(0),
+        None if base.is_negative() => return Err(CalcError::domain("result is not a real number", span)),
+        None => false,
+    };
+    // 1 and -1 stay small whatever the exponent
+    if base.abs().is_one() {
+        return Ok(if negative { -BigDecimal::one() } else { BigDecimal::one() });
+    }
+    let size: f64 = magnitude(&base) * to_f64(&exponent);
+    if size.abs() > MAX_EXPONENT || size.is_nan() {
+        return Err(CalcError::overflow(format!("decimal result of `^` would be beyond 10^{}", if size < 0f64 { -MAX_EXPONENT } else { MAX_EXPONENT }), span));
+    }
+    if let Some(exponent) = integer.as_ref().and_then(|integer| integer.to_i64()) {
+        return Ok(base.powi_with_context(exponent, &guarded_context(precision)));
+    }
+    // x^y = e^(y ln x), with enough extra digits that the error in y ln x doesn't reach the result
+    let extra: u64 = size.abs().max(1f64).log10().ceil() as u64 + 5;
+    let result: BigDecimal = (exponent * ln(&base.abs(), precision + extra)).exp_with_context(&guarded_context(precision));
+    Ok(if negative { -result } else { result })
+}
+
+fn real_from_f64(value: f64, span: Span) -> Result<BigDecimal, CalcError> {
+    if value.is_nan() {
+        return Err(CalcError::domain("result is not a real number", span));
+    }
+    from_f64(value, span)
+}
+
+// Builtins with an exact decimal implementation, `None` for the ones computed through f64
+fn exact_builtin(name: &str, args: &[BigDecimal], precision: u64, span: Span) -> Option<Result<BigDecimal, CalcError>> {
+    let ctx: Context = guarded_context(precision);
+    let x: &BigDecimal = args.first()?;
+    let result: Result<BigDecimal, CalcError> = match name {
+        "abs" => Ok(x.abs()),
+        // Left to the f64 version, which continues in the complex plane
+        "sqrt" if x.is_negative() => return None,
+        "pow" if x.is_negative() && !args[1].is_integer() => return None,
+        "sqrt" => Ok(x.sqrt_with_context(&ctx).unwrap()),
+        "cbrt" => Ok(x.cbrt_with_context(&ctx)),
+        "exp" => Ok(x.exp_with_context(&ctx)),
+        "floor" => Ok(x.with_scale_round(0, RoundingMode::Floor)),
+        "ceil" => Ok(x.with_scale_round(0, RoundingMode::Ceiling)),
+        "trunc" => Ok(x.with_scale_round(0, RoundingMode::Down)),
+        "fract" => Ok(x - x.with_scale_round(0, RoundingMode::Down)),
+        "sign" => Ok(BigDecimal::from(x.sign() as i32)),
+        "round" => args.get(1).map_or(Ok(0), |digits| round_digits(to_f64(digits), span))
+            .map(|digits| x.with_scale_round(digits as i64, RoundingMode::HalfUp)),
+        "pow" => power(x.clone(), args[1].clone(), precision, span),
+        "hypot" => (x.square() + args[1].square()).sqrt_with_context(&ctx).ok_or_else(|| CalcError::domain("sqrt of a negative number", span)),
+        "deg" => Ok(x * BigDecimal::from(180) * pi(precision + 5).inverse_with_context(&ctx)),
+        "rad" => Ok(x * pi(precision + 5) * BigDecimal::from(180).inverse_with_context(&ctx)),
+        "sin" => Ok(sin_cos(x, precision)?.0),
+        "cos" => Ok(sin_cos(x, precision)?.1),
+        "tan" => {
+            let (sin, cos) = sin_cos(x, precision + 5)?;
+            Ok(sin * cos.inverse_with_context(&ctx))
+        }
+        // Left to the f64 version, which continues in the complex plane
+        "asin" | "acos" if x.abs() > BigDecimal::one() => return None,
+        "asin" => Ok(asin(x, precision)),
+        "acos" => Ok(pi(precision + 5).half() - asin(x, precision + 5)),
+        "atan" => Ok(atan(x, precision)),
+        "atan2" => Ok(atan2(x, &args[1], precision)),
+        "arg" => Ok(if x.is_negative() { pi(precision) } else { BigDecimal::zero() }),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Calls `builtin` with at least one decimal argument, using an exact
+/// implementation where there is one and going through f64 otherwise.
+pub fn call_builtin(builtin: &Builtin, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+    let span: Span = context.span;
+    let precision: u64 = context.settings.decimal_precision();
+    let values: &[Value] = args;
+    let args: Vec<BigDecimal> = args.iter().map(|arg| match arg {
+        Value::Decimal(value) => Ok(value.clone()),
+        Value::Number(value) => from_f64(*value, span),
+        Value::Integer(value) => Ok(BigDecimal::from(value.clone())),
+        Value::Rational(value) => Ok(from_rational(value, precision)),
+        _ => Err(CalcError::type_error("Attempt to apply mathematical function on non-number", span)),
+    }).collect::<Result<Vec<BigDecimal>, CalcError>>()?;
+
+    let result: BigDecimal = if let Some(result) = exact_builtin(builtin.name, &args, precision, span) {
+        result?
+    } else {
+        let floats: Vec<f64> = args.iter().map(to_f64).collect();
+        let result: f64 = builtin.call_float(&floats, span)?;
+        // Real functions outside their domain, like sqrt(-4), continue in the complex plane
+        if result.is_nan() && !floats.iter().any(|value| value.is_nan()) {
+            return complex::call_builtin(builtin, values, context).map_err(|_| CalcError::domain("result is not a real number", span));
+        }
+        real_from_f64(result, span)?
+    };
+    Ok(Value::Decimal(round(result, precision)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::Engine, error::ErrorKind};
+
+    fn eval_at(digits: u64, input: &str) -> Result<String, ErrorKind> {
+        let mut engine: Engine = Engine::new();
+        engine.set_precision(Some(digits));
+        engine.eval(input).map(|value| value.to_string()).map_err(|error| error.kind)
+    }
+
+    #[test]
+    fn precision_setting() {
+        assert_eq!(eval_at(10, "1/3"), Ok(String::from("0.3333333333")));
+        assert_eq!(eval_at(30, "2/3"), Ok(String::from("0.666666666666666666666666666667")));
+        assert_eq!(eval_at(5, "pi"), Ok(String::from("3.1416")));
+        let mut engine: Engine = Engine::new();
+        engine.set_precision(Some(5000));
+        assert_eq!(engine.precision(), Some(1000));
+    }
+
+    #[test]
+    fn exact_literals() {
+        assert_eq!(eval_at(50, "0.1 + 0.2"), Ok(String::from("0.3")));
+        assert_eq!(eval_at(50, "0.1 + 0.2 == 0.3"), Ok(String::from("true")));
+        assert_eq!(eval_at(50, "123456789012345678901234567890 + 1"), Ok(String::from("123456789012345678901234567891")));
+        assert_eq!(eval_at(50, "1.5e-30 * 2"), Ok(String::from("3e-30")));
+    }
+
+    #[test]
+    fn powers_and_roots() {
+        let root_two: String = String::from("1.4142135623730950488016887242096980785696718753769");
+        assert_eq!(eval_at(50, "sqrt(2)"), Ok(root_two.clone()));
+        assert_eq!(eval_at(50, "2^0.5"), Ok(root_two));
+        assert_eq!(eval_at(30, "3^(1/3)"), Ok(String::from("1.44224957030740838232163831078")));
+        assert_eq!(eval_at(50, "2^-3"), Ok(String::from("0.125")));
+        assert_eq!(eval_at(50, "(-2)^3"), Ok(String::from("-8")));
+        assert_eq!(eval_at(50, "0^0"), Ok(String::from("1")));
+        assert_eq!(eval_at(50, "sqrt(-4)"), Ok(String::from("2i")));
+    }
+
+    #[test]
+    fn trig_at_full_precision() {
+        assert_eq!(eval_at(50, "sin(1)"), Ok(String::from("0.84147098480789650665250232163029899962256306079837")));
+        assert_eq!(eval_at(50, "atan(1)*4 == pi"), Ok(String::from("true")));
+        assert_eq!(eval_at(50, "asin(1)"), Ok(String::from("1.5707963267948966192313216916397514420985846996876")));
+        assert_eq!(eval_at(30, "cos(pi)"), Ok(String::from("-1")));
+        assert_eq!(eval_at(30, "atan2(-1, -1)"), Ok(String::from("-2.35619449019234492884698253746")));
+        assert_eq!(eval_at(30, "tan(10^20)"), Ok(String::from("-0.844602463019884254184093234001")));
+        assert_eq!(eval_at(50, "asin(2)").map(|value| value.ends_with('i')), Ok(true));
+        let pi: String = eval_at(1000, "pi").unwrap();
+        assert!(pi.ends_with("216420199"), "{}", &pi[pi.len() - 20..]);
+        assert_eq!(eval_at(200, "atan(1)*4 - pi"), Ok(String::from("0")));
+    }
+
+    #[test]
+    fn rounds_to_digits() {
+        assert_eq!(eval_at(50, "round(2/3, 3)"), Ok(String::from("0.667")));
+        assert_eq!(eval_at(50, "round(2.5)"), Ok(String::from("3")));
+        assert_eq!(eval_at(50, "round(1/3, 10^12)"), Err(ErrorKind::Domain));
+        assert_eq!(eval_at(50, "round(1/3, 1.5)"), Err(ErrorKind::Domain));
+    }
+
+    #[test]
+    fn large_and_small_results() {
+        assert_eq!(eval_at(50, "10^30"), Ok(String::from("1e30")));
+        assert_eq!(eval_at(50, "10^-30"), Ok(String::from("1e-30")));
+        assert_eq!(eval_at(50, "2^100"), Ok(String::from("1267650600228229401496703205376")));
+        assert_eq!(eval_at(50, "2^(10^18)"), Err(ErrorKind::Overflow));
+        assert_eq!(eval_at(50, "10^(10^9)"), Err(ErrorKind::Overflow));
+        assert_eq!(eval_at(50, "10^(-10^9)"), Err(ErrorKind::Overflow));
+        assert_eq!(eval_at(50, "0^-1"), Err(ErrorKind::Domain));
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{angle::AngleMode, builtins::SPECIAL_FORMS, complex::IMAGINARY_UNIT, decimal, environment::{Environment, HistoryEntry}, error::CalcError, format::*, functions::*, interpreter::*, lexer::Lexer, parser::*, units};
+
+/// Evaluates calculator expressions.
+///
+/// An `Engine` runs the full pipeline of lexing, parsing and evaluation.
+/// Hosts that only need one-off results can use [`eval`](crate::eval)
+/// instead. Variables assigned with `x = ...` persist for the lifetime of
+/// the engine.
+pub struct Engine {
+    env: Environment,
+    format: Format,
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    /// Most significant digits [`set_precision`](Engine::set_precision) allows.
+    pub const MAX_PRECISION: u64 = decimal::MAX_PRECISION;
+
+    pub fn new() -> Engine {
+        Engine { env: Environment::new(), format: Format::Default }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Sets the format used by [`eval_to_string`](Engine::eval_to_string)
+    /// when the input has no `to <format>` suffix.
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    pub fn precision(&self) -> Option<u64> {
+        self.env.settings().precision
+    }
+
+    /// Switches to decimal arithmetic with `digits` significant digits, or
+    /// back to f64 with `None`. Precision is capped at 1000 digits.
+    pub fn set_precision(&mut self, digits: Option<u64>) {
+        self.env.settings_mut().precision = digits.map(|digits| digits.clamp(1, Engine::MAX_PRECISION));
+    }
+
+    pub fn angle_mode(&self) -> AngleMode {
+        self.env.settings().angle
+    }
+
+    /// Sets the unit of angles taken by `sin`/`cos`/`tan` and returned by
+    /// `asin`/`acos`/`atan`/`atan2`.
+    pub fn set_angle_mode(&mut self, mode: AngleMode) {
+        self.env.settings_mut().angle = mode;
+    }
+
+    pub fn set_variable(&mut self, name: &str, value: Value) {
+        self.env.set(name, value);
+    }
+
+    /// Registers a numeric host function.
+    ///
+    /// ```
+    /// let mut engine = cli_calc::Engine::new();
+    /// engine.register("fee", 2, |args| args[0] * args[1] / 100.0);
+    /// assert_eq!(engine.eval("fee(200, 3)").unwrap().to_string(), "6");
+    /// ```
+    pub fn register<F>(&mut self, name: &str, arity: impl Into<Arity>, function: F)
+    where
+        F: Fn(&[f64]) -> f64 + 'static,
+    {
+        self.env.registry_mut().register(name, arity, function);
+    }
+
+    /// Registers any [`Function`] implementation.
+    pub fn register_function(&mut self, name: &str, function: impl Function + 'static) {
+        self.env.registry_mut().register_function(name, function);
+    }
+
+    /// Signatures of every callable function, sorted by name.
+    pub fn function_signatures(&self) -> Vec<String> {
+        self.env.registry().signatures()
+    }
+
+    /// Every name an expression can refer to: functions, constants, units
+    /// and the variables defined so far, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.env.registry().names().cloned().collect();
+        names.extend(SPECIAL_FORMS.iter().map(|(name, _)| name.to_string()));
+        names.extend(CONSTANTS.iter().map(|(name, _)| name.to_string()));
+        names.push(IMAGINARY_UNIT.to_string());
+        names.push(LAST_RESULT.to_string());
+        names.extend(units::names().map(String::from));
+        names.extend(self.env.variables().map(|(name, _)| name.clone()));
+        names.extend(self.env.functions().map(|(name, _)| name.clone()));
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Debug view of the syntax tree `input` parses to, without evaluating it.
+    pub fn syntax_tree(&self, input: &str) -> Result<String, CalcError> {
+        Ok(format!("{:?}", self.parse(input)?))
+    }
+
+    fn parse(&self, input: &str) -> Result<ASTNode, CalcError> {
+        let lexer: Lexer = Lexer::new(input)?;
+        Parser::new(lexer).parse()
+    }
+
+    fn evaluate(&mut self, node: ASTNode) -> Result<Value, CalcError> {
+        evaluate_ast(node, &mut self.env)
+    }
+
+    /// Parses and evaluates `input`, recording the result so later inputs
+    /// can refer to it as `ans` or `$1`, `$2`, ...
+    pub fn eval(&mut self, input: &str) -> Result<Value, CalcError> {
+        let node: ASTNode = self.parse(input)?;
+        let value: Value = self.evaluate(node)?;
+        self.record(input, &value);
+        Ok(value)
+    }
+
+    /// Parses, evaluates and renders `input`, recording the result like
+    /// [`eval`](Engine::eval). A `to <format>` suffix takes precedence over
+    /// the engine's format.
+    pub fn eval_to_string(&mut self, input: &str) -> Result<String, CalcError> {
+        let node: ASTNode = self.parse(input)?;
+        self.evaluate_and_render(node, input)
+    }
+
+    /// Renders `value` in the engine's format, or the default form when the
+    /// format can't represent it.
+    pub fn format_value(&self, value: &Value) -> String {
+        format_value(value, self.format).unwrap_or_else(|_| value.to_string())
+    }
+
+    /// Past inputs and their results, oldest first.
+    pub fn history(&self) -> &[HistoryEntry] {
+        self.env.history()
+    }
+
+    // Defining a function is not a result worth referring back to
+    fn record(&mut self, input: &str, value: &Value) {
+        if !matches!(value, Value::Function(..)) {
+            self.env.record(input.trim(), value.clone());
+        }
+    }
+
+    fn evaluate_and_render(&mut self, node: ASTNode, input: &str) -> Result<String, CalcError> {
+        let span = node.span;
+        let (node, format): (ASTNode, Option<Format>) = match node.kind {
+            ASTNodeKind::Format(inner_node, format) => (*inner_node, Some(format)),
+            kind => (ASTNode::new(kind, span), None),
+        };
+        let value: Value = self.evaluate(node)?;
+        let text: String = match format {
+            Some(format) => format_value(&value, format).map_err(|message| CalcError::domain(message, span))?,
+            // A number the engine-wide format can't represent is still worth showing
+            None => self.format_value(&value),
+        };
+        self.record(input, &value);
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn evaluates_through_the_engine() {
+        assert_eq!(crate::eval("2^10 + 1").unwrap(), Value::Integer(1025.into()));
+        let mut engine: Engine = Engine::new();
+        engine.set_variable("rate", Value::Number(0.5));
+        assert_eq!(engine.eval("rate * 4").unwrap().to_string(), "2");
+        assert_eq!(engine.eval_to_string("255 to hex").unwrap(), "0xff");
+        engine.set_format(Format::Binary);
+        assert_eq!(engine.eval_to_string("5").unwrap(), "0b101");
+        // A result the format can't show falls back to the default form
+        assert_eq!(engine.eval_to_string("true").unwrap(), "true");
+        assert_eq!(engine.history().len(), 4);
+        assert_eq!(engine.syntax_tree("1 +").unwrap_err().kind, ErrorKind::Parse);
+    }
+
+    #[test]
+    fn settings() {
+        let mut engine: Engine = Engine::new();
+        engine.set_precision(Some(5000));
+        assert_eq!(engine.precision(), Some(Engine::MAX_PRECISION));
+        engine.set_precision(Some(30));
+        assert_eq!(engine.eval("1/3").unwrap().to_string(), "0.333333333333333333333333333333");
+        engine.set_precision(None);
+        engine.set_angle_mode(AngleMode::Degrees);
+        assert_eq!(engine.eval("sin(90)").unwrap().to_string(), "1");
+        assert_eq!(engine.angle_mode(), AngleMode::Degrees);
+    }
+
+    #[test]
+    fn lists_names() {
+        let mut engine: Engine = Engine::new();
+        engine.eval("speed = 3").unwrap();
+        engine.eval("sq(x) = x^2").unwrap();
+        let names: Vec<String> = engine.names();
+        for name in ["sqrt", "integrate", "pi", "i", "ans", "h", "speed", "sq"] {
+            assert!(names.contains(&name.to_string()), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use crate::{angle::AngleMode, decimal, functions::FunctionRegistry, interpreter::Value, parser::ASTNode};
+
+// Deep enough for reasonable recursion, shallow enough not to blow the stack
+pub const MAX_CALL_DEPTH: usize = 128;
+
+/// Options that change how expressions are evaluated.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    /// Significant digits of decimal mode, `None` for plain f64 arithmetic.
+    pub precision: Option<u64>,
+    /// Unit of angles passed to and returned from the trig functions.
+    pub angle: AngleMode,
+}
+
+impl Settings {
+    // Decimals can outlive decimal mode in variables, so they still need a precision
+    pub fn decimal_precision(&self) -> u64 {
+        self.precision.unwrap_or(decimal::DEFAULT_PRECISION)
+    }
+}
+
+/// A past input and the value it evaluated to.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub input: String,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserFunction {
+    pub params: Vec<String>,
+    pub body: ASTNode,
+}
+
+/// Variables, user-defined functions and registered functions that persist
+/// between evaluations.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    variables: HashMap<String, Value>,
+    functions: HashMap<String, UserFunction>,
+    registry: FunctionRegistry,
+    settings: Settings,
+    history: Vec<HistoryEntry>,
+    // Parameter bindings of the user function calls currently being evaluated
+    scopes: Vec<HashMap<String, Value>>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment {
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            registry: FunctionRegistry::with_builtins(),
+            settings: Settings::default(),
+            history: Vec::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    // Function bodies only see their own parameters and the globals
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.scopes.last().and_then(|scope| scope.get(name)).or_else(|| self.variables.get(name))
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    pub fn variables(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.variables.iter()
+    }
+
+    pub fn function(&self, name: &str) -> Option<&UserFunction> {
+        self.functions.get(name)
+    }
+
+    pub fn define_function(&mut self, name: &str, function: UserFunction) {
+        self.functions.insert(name.to_string(), function);
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = (&String, &UserFunction)> {
+        self.functions.iter()
+    }
+
+    pub fn registry(&self) -> &FunctionRegistry {
+        &self.registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut FunctionRegistry {
+        &mut self.registry
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    pub fn record(&mut self, input: &str, value: Value) {
+        self.history.push(HistoryEntry { input: input.to_string(), value });
+    }
+
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    // Results are numbered from 1 like `$1`
+    pub fn result(&self, number: usize) -> Option<&Value> {
+        number.checked_sub(1).and_then(|index| self.history.get(index)).map(|entry| &entry.value)
+    }
+
+    pub fn last_result(&self) -> Option<&Value> {
+        self.history.last().map(|entry| &entry.value)
+    }
+
+    // Returns false once MAX_CALL_DEPTH is reached
+    pub fn push_scope(&mut self, scope: HashMap<String, Value>) -> bool {
+        if self.scopes.len() >= MAX_CALL_DEPTH {
+            return false;
+        }
+        self.scopes.push(scope);
+        true
+    }
+
+    // Like push_scope, but the parameters of the current call stay visible alongside `name`
+    pub fn push_binding(&mut self, name: &str, value: Value) -> bool {
+        let mut scope: HashMap<String, Value> = self.scopes.last().cloned().unwrap_or_default();
+        scope.insert(name.to_string(), value);
+        self.push_scope(scope)
+    }
+
+    // Changes a binding made by push_binding, without copying the scope again
+    pub fn rebind(&mut self, name: &str, value: Value) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), value);
+        }
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Environment {
+        Environment::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::Engine, error::ErrorKind};
+
+    #[test]
+    fn refers_to_past_results() {
+        let mut engine: Engine = Engine::new();
+        assert_eq!(engine.eval("ans").unwrap_err().kind, ErrorKind::UnknownIdentifier);
+        engine.eval("2 + 3").unwrap();
+        engine.eval("ans * 2").unwrap();
+        assert_eq!(engine.eval("$1 + _2").unwrap().to_string(), "15");
+        assert_eq!(engine.eval("ans").unwrap().to_string(), "15");
+        assert_eq!(engine.eval("$9").unwrap_err().kind, ErrorKind::UnknownIdentifier);
+        assert_eq!(engine.eval("$0").unwrap_err().kind, ErrorKind::UnknownIdentifier);
+    }
+
+    #[test]
+    fn records_history() {
+        let mut engine: Engine = Engine::new();
+        engine.eval(" x = 4 ").unwrap();
+        engine.eval("f(y) = y^2").unwrap();
+        engine.eval("f(x)").unwrap();
+        let entries: Vec<(String, String)> = engine.history().iter().map(|entry| (entry.input.clone(), entry.value.to_string())).collect();
+        assert_eq!(entries, vec![(String::from("x = 4"), String::from("4")), (String::from("f(x)"), String::from("16"))]);
+    }
+
+    #[test]
+    fn history_names_are_reserved() {
+        let mut engine: Engine = Engine::new();
+        assert_eq!(engine.eval("ans = 4").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(engine.eval("_1 = 4").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(engine.eval("$1 = 4").unwrap_err().kind, ErrorKind::Type);
+        engine.eval("5").unwrap();
+        assert_eq!(engine.eval("ans").unwrap().to_string(), "5");
+        assert_eq!(engine.eval("x_1 = 6").unwrap().to_string(), "6");
+    }
+
+    #[test]
+    fn variables_persist_between_inputs() {
+        let mut engine: Engine = Engine::new();
+        assert_eq!(engine.eval("x = 3*4").unwrap().to_string(), "12");
+        assert_eq!(engine.eval("y = x + 1").unwrap().to_string(), "13");
+        engine.eval("x = 2").unwrap();
+        assert_eq!(engine.eval("x * y").unwrap().to_string(), "26");
+        assert_eq!(engine.eval("z + 1").unwrap_err().kind, ErrorKind::UnknownIdentifier);
+        assert_eq!(engine.eval("pi = 3").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(engine.eval("2 = 3").unwrap_err().kind, ErrorKind::Parse);
+    }
+}
@@ -0,0 +1,108 @@
+use std::{error, fmt};
+
+use crate::lexer::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    Lex,
+    Parse,
+    Type,
+    Arity,
+    Domain,
+    UnknownIdentifier,
+    Overflow,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name: &str = match self {
+            ErrorKind::Lex => "lex error",
+            ErrorKind::Parse => "parse error",
+            ErrorKind::Type => "type error",
+            ErrorKind::Arity => "arity error",
+            ErrorKind::Domain => "domain error",
+            ErrorKind::UnknownIdentifier => "unknown identifier",
+            ErrorKind::Overflow => "overflow",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalcError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Span,
+}
+
+impl CalcError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>, span: Span) -> CalcError {
+        CalcError { kind, message: message.into(), span }
+    }
+
+    pub fn lex(message: impl Into<String>, span: Span) -> CalcError {
+        CalcError::new(ErrorKind::Lex, message, span)
+    }
+
+    pub fn parse(message: impl Into<String>, span: Span) -> CalcError {
+        CalcError::new(ErrorKind::Parse, message, span)
+    }
+
+    pub fn type_error(message: impl Into<String>, span: Span) -> CalcError {
+        CalcError::new(ErrorKind::Type, message, span)
+    }
+
+    pub fn arity(message: impl Into<String>, span: Span) -> CalcError {
+        CalcError::new(ErrorKind::Arity, message, span)
+    }
+
+    pub fn domain(message: impl Into<String>, span: Span) -> CalcError {
+        CalcError::new(ErrorKind::Domain, message, span)
+    }
+
+    pub fn unknown_identifier(message: impl Into<String>, span: Span) -> CalcError {
+        CalcError::new(ErrorKind::UnknownIdentifier, message, span)
+    }
+
+    pub fn overflow(message: impl Into<String>, span: Span) -> CalcError {
+        CalcError::new(ErrorKind::Overflow, message, span)
+    }
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)
+    }
+}
+
+impl error::Error for CalcError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn errors_have_kinds() {
+        assert_eq!(error_kind("2 @ 3"), ErrorKind::Lex);
+        assert_eq!(error_kind("2 + * 3"), ErrorKind::Parse);
+        assert_eq!(error_kind("1 + true"), ErrorKind::Type);
+        assert_eq!(error_kind("sqrt(1, 2)"), ErrorKind::Arity);
+        assert_eq!(error_kind("1/0"), ErrorKind::Domain);
+        assert_eq!(error_kind("foo + 1"), ErrorKind::UnknownIdentifier);
+        assert_eq!(error_kind("2^(10^7)"), ErrorKind::Overflow);
+    }
+
+    #[test]
+    fn composes_with_std_errors() {
+        fn run() -> Result<(), Box<dyn error::Error>> {
+            crate::eval("1 +")?;
+            Ok(())
+        }
+        let error: Box<dyn error::Error> = run().unwrap_err();
+        assert_eq!(error.to_string(), "parse error: unexpected end of input");
+        let error: CalcError = crate::eval("foo").unwrap_err();
+        assert_eq!((error.span.start, error.span.end), (0, 3));
+    }
+}
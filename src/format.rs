@@ -0,0 +1,260 @@
+use std::fmt;
+
+use bigdecimal::{BigDecimal, RoundingMode, Signed};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+
+use crate::{complex, decimal, interpreter::Value, rational};
+
+/// How results are rendered, chosen with `:format` or a `to <format>` suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Format {
+    Default,
+    Hex,
+    Binary,
+    Octal,
+    Scientific,
+    Engineering,
+    Fixed(usize),
+    Separated,
+}
+
+impl Format {
+    pub const NAMES: &'static [&'static str] = &["dec", "hex", "bin", "oct", "sci", "eng", "fixed N", "sep"];
+
+    /// Most digits `fixed N` takes, more than the highest decimal precision can only print padding.
+    pub const MAX_DIGITS: usize = decimal::MAX_PRECISION as usize;
+
+    pub fn from_name(name: &str, digits: Option<usize>) -> Option<Format> {
+        let format: Format = match name {
+            "dec" | "default" => Format::Default,
+            "hex" => Format::Hex,
+            "bin" => Format::Binary,
+            "oct" => Format::Octal,
+            "sci" => Format::Scientific,
+            "eng" => Format::Engineering,
+            "fixed" => return Some(Format::Fixed(digits.unwrap_or(2))),
+            "sep" => Format::Separated,
+            _ => return None,
+        };
+        // Only `fixed` takes a digit count
+        if digits.is_some() { None } else { Some(format) }
+    }
+
+    pub fn is_name(name: &str) -> bool {
+        Format::from_name(name, None).is_some()
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Default => write!(f, "dec"),
+            Format::Hex => write!(f, "hex"),
+            Format::Binary => write!(f, "bin"),
+            Format::Octal => write!(f, "oct"),
+            Format::Scientific => write!(f, "sci"),
+            Format::Engineering => write!(f, "eng"),
+            Format::Fixed(digits) => write!(f, "fixed {}", digits),
+            Format::Separated => write!(f, "sep"),
+        }
+    }
+}
+
+fn format_radix(value: f64, format: Format) -> Result<String, String> {
+    if value.fract() != 0f64 || value.abs() >= 2f64.powi(63) {
+        return Err(format!("cannot format {} as {}, it is not a 64-bit integer", value, format));
+    }
+    let sign: &str = if value < 0f64 { "-" } else { "" };
+    let magnitude: u64 = value.abs() as u64;
+    Ok(match format {
+        Format::Hex => format!("{}0x{:x}", sign, magnitude),
+        Format::Binary => format!("{}0b{:b}", sign, magnitude),
+        _ => format!("{}0o{:o}", sign, magnitude),
+    })
+}
+
+// Like scientific notation, but the exponent is always a multiple of 3
+fn format_engineering(value: f64) -> String {
+    if value == 0f64 || !value.is_finite() {
+        return format!("{}", value);
+    }
+    // Moving the point within the digits avoids scaling by 10^exponent, which underflows for values like 1e-320
+    let mut scientific: String = format!("{:e}", value.abs());
+    // Rounding long mantissas to 12 significant digits hides float noise like 0.30000000000000004
+    if scientific.split('e').next().unwrap().len() > 13 {
+        scientific = format!("{:.11e}", value.abs());
+    }
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse::<i32>().unwrap();
+    let shift: i32 = exponent.rem_euclid(3);
+    let digits: String = format!("{:0<3}", mantissa.replace('.', ""));
+    let (integer, fraction) = digits.split_at(shift as usize + 1);
+    let fraction: &str = fraction.trim_end_matches('0');
+    let sign: &str = if value < 0f64 { "-" } else { "" };
+    let point: &str = if fraction.is_empty() { "" } else { "." };
+    format!("{}{}{}{}e{}", sign, integer, point, fraction, exponent - shift)
+}
+
+// Groups the integer digits of a plain, unsigned number in threes
+fn format_separated(text: String, negative: bool) -> String {
+    let (integer, fraction) = match text.split_once('.') {
+        Some((integer, fraction)) => (integer.to_string(), format!(".{}", fraction)),
+        None => (text, String::new()),
+    };
+    let mut grouped: String = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    let sign: &str = if negative { "-" } else { "" };
+    format!("{}{}{}", sign, grouped, fraction)
+}
+
+/// Renders `value` in `format`, failing only when a number has no
+/// representation in it (e.g. `2.5` in hex).
+pub fn format_value(value: &Value, format: Format) -> Result<String, String> {
+    let number: f64 = match value {
+        Value::Number(number) => *number,
+        Value::Integer(number) => return format_integer(number, format),
+        Value::Rational(number) => return format_rational(number, format),
+        Value::Decimal(number) => return format_decimal(number, format),
+        Value::Complex(number) => return format_complex(number, format),
+        Value::Quantity(quantity) if format == Format::Default => return Ok(quantity.to_string()),
+        Value::Quantity(quantity) => return Ok(format!("{} {}", format_value(&Value::Number(quantity.value), format)?, quantity.unit)),
+        Value::List(items) => return format_list(items, format),
+        _ => return Ok(value.to_string()),
+    };
+    match format {
+        Format::Default => Ok(number.to_string()),
+        Format::Hex | Format::Binary | Format::Octal => format_radix(number, format),
+        Format::Scientific => Ok(format!("{:e}", number)),
+        Format::Engineering => Ok(format_engineering(number)),
+        Format::Fixed(digits) => Ok(format!("{:.*}", digits, number)),
+        Format::Separated => Ok(format_separated(number.abs().to_string(), number < 0f64)),
+    }
+}
+
+fn format_list(items: &[Value], format: Format) -> Result<String, String> {
+    let items: Vec<String> = items.iter().map(|item| match (item, format) {
+        // `1/3 (0.3333333333333333)` is too noisy for every element
+        (Value::Rational(_), Format::Default) => Ok(item.to_string()),
+        (item, format) => format_value(item, format),
+    }).collect::<Result<Vec<String>, String>>()?;
+    Ok(format!("[{}]", items.join(", ")))
+}
+
+fn format_big_radix(value: &BigInt, format: Format) -> String {
+    let sign: &str = if value.is_negative() { "-" } else { "" };
+    match format {
+        Format::Hex => format!("{}0x{:x}", sign, value.magnitude()),
+        Format::Binary => format!("{}0b{:b}", sign, value.magnitude()),
+        _ => format!("{}0o{:o}", sign, value.magnitude()),
+    }
+}
+
+fn format_integer(value: &BigInt, format: Format) -> Result<String, String> {
+    match format {
+        Format::Default => Ok(value.to_string()),
+        Format::Hex | Format::Binary | Format::Octal => Ok(format_big_radix(value, format)),
+        _ => format_decimal(&BigDecimal::from(value.clone()), format),
+    }
+}
+
+// Fractions show their decimal expansion alongside, e.g. `1/3 (0.3333333333333333)`
+fn format_rational(value: &BigRational, format: Format) -> Result<String, String> {
+    let number: Value = Value::Number(rational::to_f64(value));
+    match format {
+        Format::Default => Ok(format!("{} ({})", value, number)),
+        Format::Hex | Format::Binary | Format::Octal => Err(format!("cannot format {} as {}, it is not an integer", value, format)),
+        _ => format_value(&number, format),
+    }
+}
+
+// Formats the real and imaginary parts separately, e.g. `1.00 + 2.50i` for `fixed 2`
+fn format_complex(value: &Complex64, format: Format) -> Result<String, String> {
+    match format {
+        Format::Default => Ok(complex::to_string(value)),
+        Format::Hex | Format::Binary | Format::Octal => Err(format!("cannot format {} as {}, it is not an integer", complex::to_string(value), format)),
+        _ => {
+            let imaginary: String = format_value(&Value::Number(value.im.abs()), format)?;
+            let sign: &str = if value.im.is_sign_negative() { "-" } else { "" };
+            if value.re == 0f64 {
+                return Ok(format!("{}{}i", sign, imaginary));
+            }
+            let real: String = format_value(&Value::Number(value.re), format)?;
+            Ok(format!("{} {} {}i", real, if sign.is_empty() { "+" } else { "-" }, imaginary))
+        }
+    }
+}
+
+fn format_decimal(value: &BigDecimal, format: Format) -> Result<String, String> {
+    match format {
+        Format::Default => Ok(decimal::to_string(value)),
+        Format::Hex | Format::Binary | Format::Octal => match decimal::to_bigint(value) {
+            Some(integer) => Ok(format_big_radix(&integer, format)),
+            None => Err(format!("cannot format {} as {}, it is not an integer", decimal::to_string(value), format)),
+        },
+        Format::Scientific => Ok(value.normalized().to_scientific_notation()),
+        Format::Engineering => Ok(value.normalized().to_engineering_notation()),
+        Format::Fixed(digits) => Ok(value.with_scale_round(digits as i64, RoundingMode::HalfEven).to_plain_string()),
+        Format::Separated => Ok(format_separated(value.abs().to_plain_string(), value.is_negative())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::Engine, error::ErrorKind, testing::*};
+
+    fn ok(text: &str) -> Result<String, ErrorKind> {
+        Ok(String::from(text))
+    }
+
+    #[test]
+    fn radix_formats() {
+        assert_eq!(render("255 to hex"), ok("0xff"));
+        assert_eq!(render("-10 to bin"), ok("-0b1010"));
+        assert_eq!(render("8 to oct"), ok("0o10"));
+        assert_eq!(render("2^70 to hex"), ok("0x400000000000000000"));
+        assert_eq!(render("2.5 to hex"), Err(ErrorKind::Domain));
+        assert_eq!(render("1/3 to bin"), Err(ErrorKind::Domain));
+    }
+
+    #[test]
+    fn notation_formats() {
+        assert_eq!(render("12345.678 to sci"), ok("1.2345678e4"));
+        assert_eq!(render("12345 to eng"), ok("12.345e3"));
+        assert_eq!(render("0.00123 to eng"), ok("1.23e-3"));
+        assert_eq!(render("0.1 + 0.2 to eng"), ok("300e-3"));
+        assert_eq!(render("1e-320 to eng"), ok("10e-321"));
+        assert_eq!(render("1234567.891 to sep"), ok("1,234,567.891"));
+    }
+
+    #[test]
+    fn fixed_digits() {
+        assert_eq!(render("pi to fixed 4"), ok("3.1416"));
+        assert_eq!(render("2 to fixed"), ok("2.00"));
+        assert_eq!(render("1 to fixed 100000000000"), Err(ErrorKind::Parse));
+        assert_eq!(render("1 to fixed 2.5"), Err(ErrorKind::Parse));
+        assert_eq!(render("1 to hex 2"), Err(ErrorKind::Parse));
+        assert_eq!(render("1 to nonsense"), Err(ErrorKind::Parse));
+    }
+
+    #[test]
+    fn engine_format() {
+        let mut engine: Engine = Engine::new();
+        engine.set_format(Format::from_name("fixed", Some(3)).unwrap());
+        assert_eq!(engine.eval_to_string("1/3").unwrap(), "0.333");
+        assert_eq!(engine.eval_to_string("1/3 to sci").unwrap(), "3.333333333333333e-1");
+        engine.set_format(Format::Hex);
+        // Values the format can't show fall back to the default
+        assert_eq!(engine.eval_to_string("2.5").unwrap(), "2.5");
+        assert_eq!(Format::from_name("sep", Some(2)), None);
+        assert_eq!(Format::Fixed(3).to_string(), "fixed 3");
+    }
+}
@@ -0,0 +1,249 @@
+use std::{collections::BTreeMap, fmt, rc::Rc};
+
+use num_rational::BigRational;
+
+use crate::{angle::{self, AngleMode}, builtins::*, complex, decimal, environment::Settings, error::CalcError, integer, interpreter::Value, lexer::Span, numeric, rational, stats, units, vector};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Arity {
+    Fixed(usize),
+    Range(usize, usize),
+    Variadic(usize), // At least this many
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Fixed(n) => count == n,
+            Arity::Range(min, max) => (min..=max).contains(&count),
+            Arity::Variadic(min) => count >= min,
+        }
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(count: usize) -> Arity {
+        Arity::Fixed(count)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        match *self {
+            Arity::Fixed(n) => write!(f, "{} argument{}", n, plural(n)),
+            Arity::Range(min, max) => write!(f, "{} to {} arguments", min, max),
+            Arity::Variadic(min) => write!(f, "at least {} argument{}", min, plural(min)),
+        }
+    }
+}
+
+/// Where a call happens and the settings it runs under.
+pub struct CallContext<'a> {
+    pub span: Span,
+    pub settings: &'a Settings,
+}
+
+/// A function callable from expressions.
+///
+/// Arity is checked before `call`, so implementations may index `args`
+/// freely within the declared bounds.
+pub trait Function {
+    fn arity(&self) -> Arity;
+
+    fn call(&self, args: &[Value], context: &CallContext) -> Result<Value, CalcError>;
+
+    /// Parameter list shown by `:funcs`, e.g. `"x, y"`.
+    fn params(&self) -> String {
+        match self.arity() {
+            Arity::Fixed(n) => (0..n).map(|i| format!("x{}", i + 1)).collect::<Vec<String>>().join(", "),
+            _ => String::from("..."),
+        }
+    }
+}
+
+pub fn numeric_args(args: &[Value], span: Span) -> Result<Vec<f64>, CalcError> {
+    args.iter().map(|arg| {
+        numeric::to_float(arg).ok_or_else(|| CalcError::type_error("Attempt to apply mathematical function on non-number", span))
+    }).collect()
+}
+
+// Adapts a plain `Fn(&[f64]) -> f64` closure registered by a host
+struct NumericFunction<F> {
+    arity: Arity,
+    function: F,
+}
+
+impl<F: Fn(&[f64]) -> f64> Function for NumericFunction<F> {
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+        Ok(Value::Number((self.function)(&numeric_args(args, context.span)?)))
+    }
+}
+
+impl Builtin {
+    fn call_real(&self, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+        if args.iter().any(|arg| matches!(arg, Value::Decimal(_))) {
+            return decimal::call_builtin(self, args, context);
+        }
+        let exact: Option<Vec<BigRational>> = args.iter().map(|arg| match arg {
+            Value::Integer(value) => Some(BigRational::from_integer(value.clone())),
+            Value::Rational(value) => Some(value.clone()),
+            _ => None,
+        }).collect();
+        if let Some(exact) = exact {
+            return rational::call_builtin(self, &exact, context);
+        }
+        Ok(Value::Number(self.call_float(&numeric_args(args, context.span)?, context.span)?))
+    }
+
+    pub fn call_radians(&self, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+        if args.iter().any(|arg| matches!(arg, Value::Quantity(_))) {
+            return units::call_builtin(self, args, context);
+        }
+        if args.iter().any(|arg| matches!(arg, Value::Complex(_))) {
+            return complex::call_builtin(self, args, context);
+        }
+        let result: Value = self.call_real(args, context)?;
+        let is_nan = |value: &Value| matches!(value, Value::Number(value) if value.is_nan());
+        // Real functions outside their domain, like sqrt(-1), continue in the complex plane
+        if is_nan(&result) && !args.iter().any(is_nan) {
+            return complex::call_builtin(self, args, context).or(Ok(result));
+        }
+        Ok(result)
+    }
+}
+
+impl Function for Builtin {
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn call(&self, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+        if stats::is_aggregate(self.name) {
+            return stats::call_builtin(self, args, context);
+        }
+        if args.iter().any(|arg| matches!(arg, Value::List(_))) {
+            return vector::call_builtin(self, args, context);
+        }
+        if integer::is_integer_builtin(self.name) {
+            return integer::call_builtin(self, args, context);
+        }
+        match context.settings.angle {
+            AngleMode::Radians => self.call_radians(args, context),
+            mode => angle::call_builtin(self, args, context, mode),
+        }
+    }
+
+    fn params(&self) -> String {
+        self.params.to_string()
+    }
+}
+
+/// Named functions consulted when evaluating a call.
+#[derive(Clone, Default)]
+pub struct FunctionRegistry {
+    functions: BTreeMap<String, Rc<dyn Function>>,
+}
+
+impl fmt::Debug for FunctionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.functions.keys()).finish()
+    }
+}
+
+impl FunctionRegistry {
+    pub fn new() -> FunctionRegistry {
+        FunctionRegistry { functions: BTreeMap::new() }
+    }
+
+    /// A registry holding the standard math builtins.
+    pub fn with_builtins() -> FunctionRegistry {
+        let mut registry: FunctionRegistry = FunctionRegistry::new();
+        for builtin in BUILTINS {
+            registry.register_function(builtin.name, *builtin);
+        }
+        registry
+    }
+
+    /// Registers a numeric closure, replacing any function with the same name.
+    pub fn register<F>(&mut self, name: &str, arity: impl Into<Arity>, function: F)
+    where
+        F: Fn(&[f64]) -> f64 + 'static,
+    {
+        self.register_function(name, NumericFunction { arity: arity.into(), function });
+    }
+
+    /// Registers any [`Function`] implementation, replacing any function with the same name.
+    pub fn register_function(&mut self, name: &str, function: impl Function + 'static) {
+        self.functions.insert(name.to_string(), Rc::new(function));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<dyn Function>> {
+        self.functions.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.functions.keys()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    // Signatures of every registered function and special form, sorted by name
+    pub fn signatures(&self) -> Vec<String> {
+        let mut entries: Vec<(&str, String)> = self.functions.iter()
+            .map(|(name, function)| (name.as_str(), format!("{}({})", name, function.params())))
+            .collect();
+        entries.extend(SPECIAL_FORMS.iter().map(|(name, params)| (*name, format!("{}({})", name, params))));
+        entries.sort();
+        entries.into_iter().map(|(_, signature)| signature).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::Engine, error::ErrorKind};
+
+    // Host function with a value result and an optional argument
+    struct Clamp;
+
+    impl Function for Clamp {
+        fn arity(&self) -> Arity {
+            Arity::Range(1, 2)
+        }
+
+        fn call(&self, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+            let args: Vec<f64> = numeric_args(args, context.span)?;
+            Ok(Value::Number(args[0].min(args.get(1).copied().unwrap_or(1f64))))
+        }
+    }
+
+    #[test]
+    fn hosts_register_functions() {
+        let mut engine: Engine = Engine::new();
+        engine.register("fee", 2, |args| args[0] * args[1] / 100.0);
+        engine.register_function("clamp", Clamp);
+        assert_eq!(engine.eval("fee(250, 4) + clamp(7) + clamp(7, 5)").unwrap().to_string(), "16");
+        assert_eq!(engine.eval("fee(1)").unwrap_err().kind, ErrorKind::Arity);
+        assert_eq!(engine.eval("clamp(true)").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(engine.eval("fee(x) = x").unwrap_err().kind, ErrorKind::Type);
+        let signatures: Vec<String> = engine.function_signatures();
+        assert!(signatures.contains(&String::from("fee(x1, x2)")));
+        assert!(signatures.contains(&String::from("clamp(...)")));
+    }
+
+    #[test]
+    fn hosts_replace_builtins() {
+        let mut engine: Engine = Engine::new();
+        assert_eq!(engine.eval("sqrt(16)").unwrap().to_string(), "4");
+        engine.register("sqrt", 1, |args| -args[0]);
+        assert_eq!(engine.eval("sqrt(16)").unwrap().to_string(), "-16");
+    }
+}
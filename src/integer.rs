@@ -0,0 +1,453 @@
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
+
+use crate::{builtins::Builtin, decimal, error::CalcError, functions::CallContext, interpreter::Value, lexer::*, rational::MAX_BITS};
+
+pub fn parse(digits: &str) -> BigInt {
+    digits.parse::<BigInt>().unwrap()
+}
+
+// Shifting further than MAX_BITS would only build a number too large to use
+fn shift_amount(amount: &BigInt, span: Span) -> Result<usize, CalcError> {
+    match amount.to_u64() {
+        Some(amount) if amount <= MAX_BITS => Ok(amount as usize),
+        _ => Err(CalcError::overflow(format!("shift amount {} is out of range 0..{}", amount, MAX_BITS), span)),
+    }
+}
+
+/// Bitwise operators on integers of any size, treating negative numbers as
+/// infinite two's complement like `i64` does.
+pub fn bitwise_operation(operator: &Operator, a: BigInt, b: BigInt, span: Span) -> Result<BigInt, CalcError> {
+    Ok(match operator {
+        Operator::BWLeftShift => a << shift_amount(&b, span)?,
+        Operator::BWRightShift => a >> shift_amount(&b, span)?,
+        Operator::BWAnd => a & b,
+        Operator::BWOr => a | b,
+        Operator::BWXor => a ^ b,
+        _ => return Err(CalcError::parse("Invalid binary operator", span)),
+    })
+}
+
+pub fn is_bitwise(operator: &Operator) -> bool {
+    matches!(operator, Operator::BWLeftShift | Operator::BWRightShift | Operator::BWAnd | Operator::BWOr | Operator::BWXor)
+}
+
+pub fn factorial(n: &BigInt, span: Span) -> Result<BigInt, CalcError> {
+    if n.is_negative() {
+        return Err(CalcError::domain(format!("fact is only defined for non-negative integers, got {}", n), span));
+    }
+    let mut result: BigInt = BigInt::one();
+    let mut i: BigInt = BigInt::from(2);
+    while &i <= n {
+        result *= &i;
+        if result.bits() > MAX_BITS {
+            return Err(CalcError::overflow(format!("exact result of fact({}) would exceed {} bits", n, MAX_BITS), span));
+        }
+        i += 1;
+    }
+    Ok(result)
+}
+
+// Number theory builtins, which only make sense for whole numbers
+const INTEGER_BUILTINS: &[&str] = &["dfact", "fact", "factor", "fib", "gcd", "isprime", "lcm", "mod_inv", "mod_pow", "nCr", "nextprime", "nPr"];
+
+// Trial division covers factors this small, Pollard's rho the rest
+const TRIAL_DIVISION_LIMIT: u32 = 1000;
+// Pollard's rho gives up after this many steps for each of RHO_ATTEMPTS polynomials, checking the gcd once per batch
+const RHO_ITERATIONS: usize = 1 << 18;
+const RHO_ATTEMPTS: u32 = 4;
+const RHO_BATCH: usize = 128;
+
+// Each step of nCr works on a number up to MAX_BITS long, so the number of steps is limited too
+const MAX_STEPS: u64 = 50_000;
+
+// Primality tests on longer numbers take seconds each, and nextprime may need hundreds of them
+const MAX_PRIME_BITS: u64 = 1024;
+
+pub fn is_integer_builtin(name: &str) -> bool {
+    INTEGER_BUILTINS.contains(&name)
+}
+
+// Whole floats and decimals are fine, anything with a fractional part is an error rather than truncated
+pub fn to_integer(value: &Value, name: &str, span: Span) -> Result<BigInt, CalcError> {
+    let integer: Option<BigInt> = match value {
+        Value::Integer(value) => Some(value.clone()),
+        Value::Number(value) if value.is_finite() && value.fract() == 0f64 => BigInt::from_f64(*value),
+        Value::Decimal(value) => decimal::to_bigint(value),
+        _ => None,
+    };
+    integer.ok_or_else(|| CalcError::domain(format!("{} is only defined for integers, got {}", name, value), span))
+}
+
+fn non_negative(n: &BigInt, name: &str, span: Span) -> Result<(), CalcError> {
+    if n.is_negative() {
+        return Err(CalcError::domain(format!("{} is only defined for non-negative integers, got {}", name, n), span));
+    }
+    Ok(())
+}
+
+fn check_size(result: &BigInt, name: &str, span: Span) -> Result<(), CalcError> {
+    if result.bits() > MAX_BITS {
+        return Err(CalcError::overflow(format!("exact result of {} would exceed {} bits", name, MAX_BITS), span));
+    }
+    Ok(())
+}
+
+pub fn gcd(a: &BigInt, b: &BigInt) -> BigInt {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while !b.is_zero() {
+        let remainder: BigInt = &a % &b;
+        a = b;
+        b = remainder;
+    }
+    a
+}
+
+fn lcm(a: &BigInt, b: &BigInt) -> BigInt {
+    if a.is_zero() || b.is_zero() {
+        return BigInt::zero();
+    }
+    (a * b).abs() / gcd(a, b)
+}
+
+// n!! = n * (n - 2) * (n - 4) * ..., with (-1)!! = 0!! = 1
+fn double_factorial(n: &BigInt, span: Span) -> Result<BigInt, CalcError> {
+    if n < &BigInt::from(-1) {
+        return Err(CalcError::domain(format!("dfact is only defined for integers from -1, got {}", n), span));
+    }
+    let mut result: BigInt = BigInt::one();
+    let mut i: BigInt = n.clone();
+    while i > BigInt::one() {
+        result *= &i;
+        check_size(&result, &format!("dfact({})", n), span)?;
+        i -= 2;
+    }
+    Ok(result)
+}
+
+fn choose(n: &BigInt, k: &BigInt, span: Span) -> Result<BigInt, CalcError> {
+    non_negative(n, "nCr", span)?;
+    non_negative(k, "nCr", span)?;
+    if k > n {
+        return Ok(BigInt::zero());
+    }
+    let k: BigInt = k.clone().min(n - k);
+    if k > BigInt::from(MAX_STEPS) {
+        return Err(CalcError::overflow(format!("nCr is limited to {} steps, the smaller of k and n - k is {}", MAX_STEPS, k), span));
+    }
+    let mut result: BigInt = BigInt::one();
+    let mut i: BigInt = BigInt::one();
+    while i <= k {
+        // Dividing as we go stays exact, every prefix is itself a binomial coefficient
+        result = result * (n - &k + &i) / &i;
+        check_size(&result, &format!("nCr({}, {})", n, k), span)?;
+        i += 1;
+    }
+    Ok(result)
+}
+
+fn permutations(n: &BigInt, k: &BigInt, span: Span) -> Result<BigInt, CalcError> {
+    non_negative(n, "nPr", span)?;
+    non_negative(k, "nPr", span)?;
+    if k > n {
+        return Ok(BigInt::zero());
+    }
+    let mut result: BigInt = BigInt::one();
+    let mut i: BigInt = n - k + 1;
+    while &i <= n {
+        result *= &i;
+        check_size(&result, &format!("nPr({}, {})", n, k), span)?;
+        i += 1;
+    }
+    Ok(result)
+}
+
+// Fast doubling: F(2k) = F(k) * (2F(k+1) - F(k)), F(2k+1) = F(k)^2 + F(k+1)^2
+fn fibonacci_pair(n: u64) -> (BigInt, BigInt) {
+    if n == 0 {
+        return (BigInt::zero(), BigInt::one());
+    }
+    let (a, b) = fibonacci_pair(n / 2);
+    let c: BigInt = &a * (&b * 2 - &a);
+    let d: BigInt = &a * &a + &b * &b;
+    if n.is_multiple_of(2) { (c, d) } else { (d.clone(), c + d) }
+}
+
+fn fibonacci(n: &BigInt, span: Span) -> Result<BigInt, CalcError> {
+    non_negative(n, "fib", span)?;
+    let overflow = || CalcError::overflow(format!("exact result of fib({}) would exceed {} bits", n, MAX_BITS), span);
+    // F(n) has about 0.694n bits, the exact check below settles the numbers close to the limit
+    let result: BigInt = match n.to_u64() {
+        Some(n) if n <= MAX_BITS * 3 / 2 => fibonacci_pair(n).0,
+        _ => return Err(overflow()),
+    };
+    if result.bits() > MAX_BITS {
+        return Err(overflow());
+    }
+    Ok(result)
+}
+
+fn prime_size(n: &BigInt, name: &str, span: Span) -> Result<(), CalcError> {
+    if n.bits() > MAX_PRIME_BITS {
+        return Err(CalcError::overflow(format!("{} is limited to numbers of at most {} bits", name, MAX_PRIME_BITS), span));
+    }
+    Ok(())
+}
+
+// Miller-Rabin with the first 13 primes as witnesses, exact below 3.3 * 10^24 and a strong probable prime test above
+pub fn is_prime(n: &BigInt) -> bool {
+    const WITNESSES: [u32; 13] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41];
+    if n < &BigInt::from(2) {
+        return false;
+    }
+    for witness in WITNESSES {
+        let witness: BigInt = BigInt::from(witness);
+        if n == &witness {
+            return true;
+        }
+        if (n % &witness).is_zero() {
+            return false;
+        }
+    }
+    let n_minus_one: BigInt = n - 1;
+    let shift: u64 = n_minus_one.trailing_zeros().unwrap_or(0);
+    let odd: BigInt = &n_minus_one >> shift;
+    'witness: for witness in WITNESSES {
+        let mut x: BigInt = BigInt::from(witness).modpow(&odd, n);
+        if x.is_one() || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..shift {
+            x = &x * &x % n;
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+fn next_prime(n: &BigInt) -> BigInt {
+    if n < &BigInt::from(2) {
+        return BigInt::from(2);
+    }
+    // Only odd candidates past 2
+    let mut candidate: BigInt = (n + 1) | BigInt::one();
+    while !is_prime(&candidate) {
+        candidate += 2;
+    }
+    candidate
+}
+
+// Some non-trivial divisor of the composite `n`, or None if none turned up within the step budget
+fn pollard_rho(n: &BigInt) -> Option<BigInt> {
+    for c in 1..=RHO_ATTEMPTS {
+        let step = |x: &BigInt| (x * x + c) % n;
+        let (mut x, mut y) = (BigInt::from(2), BigInt::from(2));
+        for _ in 0..RHO_ITERATIONS / RHO_BATCH {
+            // One gcd per batch on the product of the differences, replaying the batch if it hit
+            let (start_x, start_y) = (x.clone(), y.clone());
+            let mut product: BigInt = BigInt::one();
+            for _ in 0..RHO_BATCH {
+                x = step(&x);
+                y = step(&step(&y));
+                product = product * (&x - &y) % n;
+            }
+            if gcd(&product, n).is_one() {
+                continue;
+            }
+            (x, y) = (start_x, start_y);
+            for _ in 0..RHO_BATCH {
+                x = step(&x);
+                y = step(&step(&y));
+                let divisor: BigInt = gcd(&(&x - &y), n);
+                if !divisor.is_one() && divisor != *n {
+                    return Some(divisor);
+                }
+            }
+            break; // Cycle without a divisor, try the next c
+        }
+    }
+    None
+}
+
+fn factorize(n: BigInt, factors: &mut Vec<BigInt>, span: Span) -> Result<(), CalcError> {
+    if n.is_one() {
+        return Ok(());
+    }
+    if n.bits() > MAX_PRIME_BITS {
+        return Err(CalcError::domain(format!("factor gave up on {}, it is too large to test for primality", n), span));
+    }
+    if is_prime(&n) {
+        factors.push(n);
+        return Ok(());
+    }
+    match pollard_rho(&n) {
+        Some(divisor) => {
+            let cofactor: BigInt = &n / &divisor;
+            factorize(divisor, factors, span)?;
+            factorize(cofactor, factors, span)
+        }
+        None => Err(CalcError::domain(format!("factor gave up on {}, its prime factors are too large to find", n), span)),
+    }
+}
+
+// Prime factors in ascending order, repeated by multiplicity so their product is `n`
+fn prime_factors(n: &BigInt, span: Span) -> Result<Vec<BigInt>, CalcError> {
+    if !n.is_positive() {
+        return Err(CalcError::domain(format!("factor is only defined for positive integers, got {}", n), span));
+    }
+    let mut factors: Vec<BigInt> = Vec::new();
+    let mut n: BigInt = n.clone();
+    for divisor in 2..TRIAL_DIVISION_LIMIT {
+        while (&n % divisor).is_zero() {
+            factors.push(BigInt::from(divisor));
+            n /= divisor;
+        }
+    }
+    let mut large: Vec<BigInt> = Vec::new();
+    factorize(n, &mut large, span)?;
+    large.sort();
+    factors.extend(large);
+    Ok(factors)
+}
+
+fn modulus(value: &BigInt, name: &str, span: Span) -> Result<(), CalcError> {
+    if !value.is_positive() {
+        return Err(CalcError::domain(format!("{} needs a positive modulus, got {}", name, value), span));
+    }
+    Ok(())
+}
+
+fn modular_inverse(a: &BigInt, m: &BigInt, span: Span) -> Result<BigInt, CalcError> {
+    modulus(m, "mod_inv", span)?;
+    a.modinv(m).ok_or_else(|| CalcError::domain(format!("{} has no inverse modulo {}", a, m), span))
+}
+
+fn modular_power(base: &BigInt, exponent: &BigInt, m: &BigInt, span: Span) -> Result<BigInt, CalcError> {
+    modulus(m, "mod_pow", span)?;
+    // A negative exponent is a power of the inverse
+    if exponent.is_negative() {
+        return Ok(modular_inverse(base, m, span)?.modpow(&-exponent, m));
+    }
+    Ok(base.modpow(exponent, m))
+}
+
+/// Calls one of the number theory builtins, which work on exact integers
+/// and reject anything with a fractional part.
+pub fn call_builtin(builtin: &Builtin, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+    let span: Span = context.span;
+    let args: Vec<BigInt> = args.iter().map(|arg| to_integer(arg, builtin.name, span)).collect::<Result<Vec<BigInt>, CalcError>>()?;
+    let result: BigInt = match builtin.name {
+        "gcd" => gcd(&args[0], &args[1]),
+        "lcm" => lcm(&args[0], &args[1]),
+        "nCr" => choose(&args[0], &args[1], span)?,
+        "nPr" => permutations(&args[0], &args[1], span)?,
+        "fact" => factorial(&args[0], span)?,
+        "dfact" => double_factorial(&args[0], span)?,
+        "fib" => fibonacci(&args[0], span)?,
+        "isprime" => {
+            prime_size(&args[0], "isprime", span)?;
+            return Ok(Value::Bool(is_prime(&args[0])));
+        }
+        "nextprime" => {
+            prime_size(&args[0], "nextprime", span)?;
+            next_prime(&args[0])
+        }
+        // A plain list with repeats rather than `2^3 * 3^2 * 5`, so the result still works with `product`, `len` and indexing
+        "factor" => return Ok(Value::List(prime_factors(&args[0], span)?.into_iter().map(Value::Integer).collect())),
+        "mod_inv" => modular_inverse(&args[0], &args[1], span)?,
+        "mod_pow" => modular_power(&args[0], &args[1], &args[2], span)?,
+        name => return Err(CalcError::unknown_identifier(format!("unknown function `{}`", name), span)),
+    };
+    Ok(Value::Integer(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::ErrorKind, testing::*};
+
+    fn int(value: i64) -> BigInt {
+        BigInt::from(value)
+    }
+
+    #[test]
+    fn gcd_and_lcm() {
+        assert_eq!(gcd(&int(12), &int(18)), int(6));
+        assert_eq!(gcd(&int(-12), &int(18)), int(6));
+        assert_eq!(gcd(&int(0), &int(5)), int(5));
+        assert_eq!(lcm(&int(4), &int(6)), int(12));
+        assert_eq!(lcm(&int(0), &int(6)), int(0));
+    }
+
+    #[test]
+    fn primality() {
+        let primes: Vec<i64> = (0..50).filter(|n| is_prime(&int(*n))).collect();
+        assert_eq!(primes, vec![2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47]);
+        assert!(is_prime(&((int(1) << 61) - 1)));
+        assert!(!is_prime(&int(561))); // Carmichael number
+        assert_eq!(next_prime(&int(100)), int(101));
+        assert_eq!(next_prime(&int(-5)), int(2));
+        assert_eq!(next_prime(&int(2)), int(3));
+        assert_eq!(error_kind("isprime(2^11213 - 1)"), ErrorKind::Overflow);
+        assert_eq!(error_kind("nextprime(2^3000)"), ErrorKind::Overflow);
+    }
+
+    #[test]
+    fn fibonacci_limit() {
+        assert_eq!(eval("fib(90)"), "2880067194370816120");
+        assert!(fibonacci(&int(1510000), Span::default()).unwrap().bits() <= MAX_BITS);
+        assert_eq!(error_kind("fib(1510700)"), ErrorKind::Overflow);
+    }
+
+    #[test]
+    fn factoring() {
+        let span: Span = Span::default();
+        assert_eq!(prime_factors(&int(360), span).unwrap(), vec![int(2), int(2), int(2), int(3), int(3), int(5)]);
+        assert_eq!(prime_factors(&int(600851475143), span).unwrap(), vec![int(71), int(839), int(1471), int(6857)]);
+        assert_eq!(eval("factor((2^31 - 1)*(2^61 - 1))"), "[2147483647, 2305843009213693951]");
+        assert_eq!(prime_factors(&int(0), span).unwrap_err().kind, ErrorKind::Domain);
+        assert_eq!(eval("product(factor(360))"), "360");
+    }
+
+    #[test]
+    fn combinatorics() {
+        let span: Span = Span::default();
+        assert_eq!(choose(&int(10), &int(3), span).unwrap(), int(120));
+        assert_eq!(choose(&int(3), &int(10), span).unwrap(), int(0));
+        assert_eq!(permutations(&int(5), &int(2), span).unwrap(), int(20));
+        assert_eq!(fibonacci(&int(10), span).unwrap(), int(55));
+        assert_eq!(double_factorial(&int(7), span).unwrap(), int(105));
+        assert_eq!(choose(&int(1_000_000), &int(500_000), span).unwrap_err().kind, ErrorKind::Overflow);
+    }
+
+    #[test]
+    fn modular_arithmetic() {
+        assert_eq!(eval("mod_pow(2, 10, 1000)"), "24");
+        assert_eq!(eval("mod_inv(3, 11)"), "4");
+        assert_eq!(eval("mod_pow(3, -1, 11)"), "4");
+        assert_eq!(error_kind("mod_inv(2, 4)"), ErrorKind::Domain);
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        assert_eq!(eval("1e20 << 1"), "200000000000000000000");
+        assert_eq!(eval("1e20 & 1"), "0");
+        assert_eq!(error_kind("2.5 & 1"), ErrorKind::Domain);
+    }
+
+    #[test]
+    fn big_integers() {
+        assert_eq!(eval("fact(500)").len(), 1135);
+        assert!(eval("fact(500)").ends_with(&"0".repeat(124)));
+        assert_eq!(eval("2^4096").len(), 1234);
+        assert_eq!(eval("2^4096 - 2^4096 + fact(5.0)"), "120");
+        assert_eq!(eval("(1 << 300) >> 299"), "2");
+        assert_eq!(eval("(2^200 + 5) & 7"), "5");
+        assert_eq!(eval("~(2^100) + 2^100"), "-1");
+        assert_eq!(eval("(2^100 | 1) - 2^100"), "1");
+        assert_eq!(error_kind("2^(10^7)"), ErrorKind::Overflow);
+    }
+}
@@ -1,170 +1,285 @@
-use crate::{lexer::*, parser::*};
+use std::{cmp::Ordering, collections::HashMap, fmt, rc::Rc};
 
-#[derive(Debug, PartialEq, PartialOrd)]
-pub enum InterpreterResult {
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+
+use crate::{builtins::*, calculus, complex, decimal, environment::*, error::CalcError, functions::*, lexer::*, numeric, parser::*, symbolic, units::{self, Quantity, Unit}, vector};
+
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Value {
     Number(f64),
+    // Exact integer of any size, what integer literals evaluate to outside decimal mode
+    Integer(BigInt),
+    // Exact fraction that isn't a whole number, e.g. the result of `1/3`
+    Rational(BigRational),
+    Complex(Complex64),
+    // Number with a unit like `5 km`
+    Quantity(Quantity),
+    // Arbitrary-precision number, produced while `:precision` is set
+    Decimal(BigDecimal),
     Bool(bool),
+    // Vector like `[1, 2, 3]`, a list of equally long lists is a matrix
+    List(Vec<Value>),
+    // Result of a definition like `f(x, y) = x^2 + y`
+    Function(String, Vec<String>),
+    // Unevaluated result of `diff` or `simplify` that still contains variables
+    Expression(Box<ASTNode>),
 }
 
-fn factorial(num: f64) -> f64 {
-    let mut result: f64 = 1f64;
-    for n in 2..=(num as i64) {
-        result *= n as f64;
+pub const CONSTANTS: &[(&str, f64)] = &[
+    ("pi", std::f64::consts::PI),
+    ("e", std::f64::consts::E),
+    ("tau", std::f64::consts::TAU),
+    ("phi", 1.618033988749895_f64),
+];
+
+// Name of the most recent result, `$1`, `$2`, ... name the earlier ones
+pub const LAST_RESULT: &str = "ans";
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::Rational(value) => write!(f, "{}", value),
+            Value::Complex(value) => write!(f, "{}", complex::to_string(value)),
+            Value::Quantity(value) => write!(f, "{}", value),
+            Value::Decimal(value) => write!(f, "{}", decimal::to_string(value)),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::List(items) => write!(f, "[{}]", items.iter().map(|item| item.to_string()).collect::<Vec<String>>().join(", ")),
+            Value::Function(name, params) => write!(f, "{}({})", name, params.join(", ")),
+            Value::Expression(expression) => write!(f, "{}", expression),
+        }
     }
-    result
 }
 
-fn perform_arithmetic_operator(left_result: InterpreterResult, right_result: InterpreterResult, operation: Box<dyn Fn(f64, f64) -> f64>) -> Result<f64, String> {
-    if let (InterpreterResult::Number(left_value), InterpreterResult::Number(right_value)) = (left_result, right_result) {
+fn perform_logical_operator(left_result: Value, right_result: Value, span: Span, operation: Box<dyn Fn(bool, bool) -> bool>) -> Result<bool, CalcError> {
+    if let (Value::Bool(left_value), Value::Bool(right_value)) = (left_result, right_result) {
         Ok(operation(left_value, right_value))
     } else {
-        Err(String::from("Attempt to perform arithmetic/bitwise operators on boolean values"))
+        Err(CalcError::type_error("Attempt to perform logical operators on non-boolean values", span))
     }
 }
 
-fn perform_logical_operator(left_result: InterpreterResult, right_result: InterpreterResult, operation: Box<dyn Fn(bool, bool) -> bool>) -> Result<bool, String> {
-    if let (InterpreterResult::Bool(left_value), InterpreterResult::Bool(right_value)) = (left_result, right_result) {
-        Ok(operation(left_value, right_value))
-    } else {
-        Err(String::from("Attempt to perform logical operators on non-boolean values"))
+// `if(cond, a, b)` only evaluates the branch it takes, which is what makes recursion terminate
+fn evaluate_if(arg_nodes: Vec<ASTNode>, span: Span, env: &mut Environment) -> Result<Value, CalcError> {
+    if arg_nodes.len() != 3 {
+        return Err(CalcError::arity(format!("if expects 3 arguments, got {}", arg_nodes.len()), span));
     }
+    let mut arg_nodes = arg_nodes.into_iter();
+    let condition_node: ASTNode = arg_nodes.next().unwrap();
+    let condition_span: Span = condition_node.span;
+    let (then_node, else_node) = (arg_nodes.next().unwrap(), arg_nodes.next().unwrap());
+    match evaluate_ast(condition_node, env)? {
+        Value::Bool(true) => evaluate_ast(then_node, env),
+        Value::Bool(false) => evaluate_ast(else_node, env),
+        _ => Err(CalcError::type_error("Condition of if must be a boolean", condition_span)),
+    }
+}
+
+fn call_user_function(function_name: &str, function: UserFunction, args: Vec<Value>, span: Span, env: &mut Environment) -> Result<Value, CalcError> {
+    if args.len() != function.params.len() {
+        return Err(CalcError::arity(format!("{} expects {} argument(s), got {}", function_name, function.params.len(), args.len()), span));
+    }
+    let scope: HashMap<String, Value> = function.params.into_iter().zip(args).collect();
+    if !env.push_scope(scope) {
+        return Err(CalcError::overflow(format!("maximum call depth of {} exceeded in `{}`", MAX_CALL_DEPTH, function_name), span));
+    }
+    let result = evaluate_ast(function.body, env);
+    env.pop_scope();
+    // The body's spans refer to the line it was defined on, so point at the call instead
+    result.map_err(|mut error| { error.span = span; error })
+}
+
+// `$2` and `_2` both refer to the second result
+fn history_number(name: &str) -> Option<usize> {
+    name.strip_prefix(['$', '_']).and_then(|digits| digits.parse::<usize>().ok())
 }
 
-pub fn evaluate_ast(node: ASTNode) -> Result<InterpreterResult, String> {
-    match node {
-        ASTNode::Number(value) => Ok(InterpreterResult::Number(value)),
-        ASTNode::Bool(value) => Ok(InterpreterResult::Bool(value)),
+pub fn evaluate_ast(node: ASTNode, env: &mut Environment) -> Result<Value, CalcError> {
+    let span: Span = node.span;
+    match node.kind {
+        ASTNodeKind::Number(literal) => numeric::parse_literal(&literal, env.settings(), span),
+        ASTNodeKind::Bool(value) => Ok(Value::Bool(value)),
 
-        ASTNode::Comparison(operators, operands) => {
+        ASTNodeKind::Comparison(operators, operands) => {
             let mut result: bool = true;
-            let mut eval_operands: Vec<InterpreterResult> = vec![];
+            let mut eval_operands: Vec<Value> = vec![];
             for operand in operands.iter() {
-                eval_operands.push(evaluate_ast(operand.clone())?);
+                eval_operands.push(evaluate_ast(operand.clone(), env)?);
             }
             for (i, operator) in operators.iter().enumerate() {
-                let (left, right) = (&eval_operands[i], &eval_operands[i+1]);
+                let ordering: Option<Ordering> = match operator {
+                    Operator::Equal | Operator::NotEqual => numeric::compare(&eval_operands[i], &eval_operands[i+1]),
+                    _ => numeric::order(operator, &eval_operands[i], &eval_operands[i+1], span)?,
+                };
                 match operator {
-                    Operator::Equal => result &= left == right,
-                    Operator::NotEqual => result &= left != right,
-                    Operator::Greater => result &= left > right,
-                    Operator::GreaterEqual => result &= left >= right,
-                    Operator::Lesser => result &= left < right,
-                    Operator::LesserEqual => result &= left <= right,
-                    _ => return Err(String::from("Invalid comparison operator"))
+                    Operator::Equal => result &= ordering == Some(Ordering::Equal),
+                    Operator::NotEqual => result &= ordering != Some(Ordering::Equal),
+                    Operator::Greater => result &= ordering == Some(Ordering::Greater),
+                    Operator::GreaterEqual => result &= matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                    Operator::Lesser => result &= ordering == Some(Ordering::Less),
+                    Operator::LesserEqual => result &= matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    _ => return Err(CalcError::parse("Invalid comparison operator", span))
                 }
             }
-            Ok(InterpreterResult::Bool(result))
+            Ok(Value::Bool(result))
         }
         
-        ASTNode::Binary(operator, left_node, right_node) => {
-            let (left_result, right_result) = (evaluate_ast(*left_node)?, evaluate_ast(*right_node)?);
+        ASTNodeKind::Binary(operator, left_node, right_node) => {
+            let (left_result, right_result) = (evaluate_ast(*left_node, env)?, evaluate_ast(*right_node, env)?);
             match operator {
-                Operator::And => Ok(InterpreterResult::Bool(perform_logical_operator(left_result, right_result, Box::new(|a, b| a && b))?)),
-                Operator::Or => Ok(InterpreterResult::Bool(perform_logical_operator(left_result, right_result, Box::new(|a, b| a || b))?)),
+                Operator::And => Ok(Value::Bool(perform_logical_operator(left_result, right_result, span, Box::new(|a, b| a && b))?)),
+                Operator::Or => Ok(Value::Bool(perform_logical_operator(left_result, right_result, span, Box::new(|a, b| a || b))?)),
 
-                Operator::Add => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| a + b))?)),
-                Operator::Subtract => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| a - b))?)),
-                Operator::Multiply => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| a * b))?)),
-                Operator::Divide => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| a / b))?)),
-                Operator::Modulo => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| a % b))?)),
-                Operator::Exponent => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| a.powf(b)))?)),
-
-                Operator::BWLeftShift => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| ((a as i64) << (b as i64)) as f64))?)),
-                Operator::BWRightShift => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| ((a as i64) >> (b as i64)) as f64))?)),
-                Operator::BWAnd => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| ((a as i64) & (b as i64)) as f64))?)),
-                Operator::BWOr => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| ((a as i64) | (b as i64)) as f64))?)),
-                Operator::BWXor => Ok(InterpreterResult::Number(perform_arithmetic_operator(left_result, right_result, Box::new(|a, b| ((a as i64) ^ (b as i64)) as f64))?)),
-
-                _ => Err(String::from("Invalid binary operator"))
+                _ => numeric::binary_operation(&operator, left_result, right_result, span, env.settings()),
             }
         }
 
-        ASTNode::Unary(operator, operand_node) => {
-            let operand_result = evaluate_ast(*operand_node)?;
+        ASTNodeKind::Unary(operator, operand_node) => {
+            let operand_result = evaluate_ast(*operand_node, env)?;
             match operator {
-                Operator::Subtract => {
-                    if let InterpreterResult::Number(value) = operand_result {
-                        Ok(InterpreterResult::Number(-value))
-                    } else {
-                        Err(String::from("Attempt to perform arithmetic negation on boolean"))
-                    }
-                },
+                Operator::Subtract => numeric::negate(operand_result, span),
                 Operator::Not => {
-                    if let InterpreterResult::Bool(value) = operand_result {
-                        Ok(InterpreterResult::Bool(!value))
+                    if let Value::Bool(value) = operand_result {
+                        Ok(Value::Bool(!value))
                     } else {
-                        Err(String::from("Attempt to perform logical not on number"))
-                    }
-                }
-                Operator::BWNot => {
-                    if let InterpreterResult::Number(value) = operand_result {
-                        Ok(InterpreterResult::Number((!(value as i64)) as f64))
-                    } else {
-                        Err(String::from("Attempt to perform bitwise not on boolean"))
+                        Err(CalcError::type_error("Attempt to perform logical not on number", span))
                     }
                 }
+                Operator::BWNot => numeric::bitwise_not(operand_result, span),
                 _ => Ok(operand_result)
             }
         }
 
-        ASTNode::Function(function_name, arg_nodes) => {
-            let mut args: Vec<f64> = Vec::new();
+        ASTNodeKind::FunctionDef(function_name, params, body) => {
+            if is_special_form(&function_name) || env.registry().contains(&function_name) {
+                return Err(CalcError::type_error(format!("cannot redefine builtin function `{}`", function_name), span));
+            }
+            env.define_function(&function_name, UserFunction { params: params.clone(), body: *body });
+            Ok(Value::Function(function_name, params))
+        }
 
+        ASTNodeKind::Function(function_name, arg_nodes) => {
+            if function_name == "if" {
+                return evaluate_if(arg_nodes, span, env);
+            }
+            if calculus::is_calculus_form(&function_name) {
+                return calculus::evaluate_form(&function_name, arg_nodes, span, env);
+            }
+            if symbolic::is_symbolic_form(&function_name) {
+                return symbolic::evaluate_form(&function_name, arg_nodes, span, env);
+            }
+
+            let mut values: Vec<Value> = Vec::new();
             for arg_node in arg_nodes.iter() {
-                let arg_result = evaluate_ast(arg_node.clone())?;
-                if let InterpreterResult::Number(value) = arg_result {
-                    args.push(value);
-                } else {
-                    return Err(String::from("Attempt to apply mathematical function on boolean"))
-                }
+                values.push(evaluate_ast(arg_node.clone(), env)?);
+            }
+
+            if let Some(function) = env.function(&function_name).cloned() {
+                return call_user_function(&function_name, function, values, span, env);
             }
 
-            match function_name.as_str() {
-                "abs" => Ok(InterpreterResult::Number(args[0].abs())),
-                "sin" => Ok(InterpreterResult::Number(args[0].sin())),
-                "cos" => Ok(InterpreterResult::Number(args[0].cos())),
-                "tan" => Ok(InterpreterResult::Number(args[0].tan())),
-                "asin" => Ok(InterpreterResult::Number(args[0].asin())),
-                "atan" => Ok(InterpreterResult::Number(args[0].atan())),
-                "atan2" => Ok(InterpreterResult::Number(args[0].atan2(args[1]))),
-                "ln" => Ok(InterpreterResult::Number(args[0].ln())),
-                "log10" => Ok(InterpreterResult::Number(args[0].log10())),
-                "log2" => Ok(InterpreterResult::Number(args[0].log2())),
-                "sqrt" => Ok(InterpreterResult::Number(args[0].sqrt())),
-                "cbrt" => Ok(InterpreterResult::Number(args[0].cbrt())),
-                "exp" => Ok(InterpreterResult::Number(args[0].exp())),
-                "floor" => Ok(InterpreterResult::Number(args[0].floor())),
-                "ceil" => Ok(InterpreterResult::Number(args[0].ceil())),
-                "round" => Ok(InterpreterResult::Number(args[0].round())),
-                "trunc" => Ok(InterpreterResult::Number(args[0].trunc())),
-                "fact" => Ok(InterpreterResult::Number(factorial(args[0]))),
-                "fract" => Ok(InterpreterResult::Number(args[0].fract())),
-                "sign" => Ok(InterpreterResult::Number(args[0].signum())),
-                "sinh" => Ok(InterpreterResult::Number(args[0].sinh())),
-                "cosh" => Ok(InterpreterResult::Number(args[0].cosh())),
-                "tanh" => Ok(InterpreterResult::Number(args[0].tanh())),
-                "asinh" => Ok(InterpreterResult::Number(args[0].asinh())),
-                "acosh" => Ok(InterpreterResult::Number(args[0].acosh())),
-                "atanh" => Ok(InterpreterResult::Number(args[0].atanh())),
-                "log" => Ok(InterpreterResult::Number(args[1].log(args[0]))),
-                "hypot" => Ok(InterpreterResult::Number(args[0].hypot(args[1]))),
-                "max" => Ok(InterpreterResult::Number(args[0].max(args[1]))),
-                "min" => Ok(InterpreterResult::Number(args[0].min(args[1]))),
-                "deg" => Ok(InterpreterResult::Number(args[0].to_degrees())),
-                "rad" => Ok(InterpreterResult::Number(args[0].to_radians())),
-                "root" => Ok(InterpreterResult::Number(args[1].powf(1f64/args[0]))),
-                "pow" => Ok(InterpreterResult::Number(args[0].powf(args[1]))),
-                "sum" => Ok(InterpreterResult::Number(args.into_iter().sum())),
-                _ => Ok(InterpreterResult::Number(args[0]))
+            let function: Rc<dyn Function> = match env.registry().get(&function_name) {
+                Some(function) => function,
+                None => return Err(CalcError::unknown_identifier(format!("unknown function `{}`", function_name), span)),
+            };
+            if !function.arity().accepts(values.len()) {
+                return Err(CalcError::arity(format!("{}({}) expects {}, got {}", function_name, function.params(), function.arity(), values.len()), span));
+            }
+            function.call(&values, &CallContext { span, settings: env.settings() })
+        }
+
+        // Formatting only affects display, see Engine::eval_to_string
+        ASTNodeKind::Format(inner_node, _) => evaluate_ast(*inner_node, env),
+
+        ASTNodeKind::Convert(inner_node, unit) => units::convert(&evaluate_ast(*inner_node, env)?, &unit, span),
+
+        ASTNodeKind::List(item_nodes) => {
+            let mut items: Vec<Value> = Vec::new();
+            for item_node in item_nodes {
+                items.push(evaluate_ast(item_node, env)?);
+            }
+            Ok(Value::List(items))
+        }
+
+        ASTNodeKind::Index(list_node, index_node) => {
+            let (list, index) = (evaluate_ast(*list_node, env)?, evaluate_ast(*index_node, env)?);
+            vector::index(&list, &index, span)
+        }
+
+        ASTNodeKind::Assign(variable_name, value_node) => {
+            // Shadowing these would silently change every later expression that uses them
+            let reserved: Option<&str> = match variable_name.as_str() {
+                name if CONSTANTS.iter().any(|(constant, _)| *constant == name) => Some("constant"),
+                complex::IMAGINARY_UNIT => Some("imaginary unit"),
+                name if name == LAST_RESULT || history_number(name).is_some() => Some("history reference"),
+                _ => None,
+            };
+            if let Some(reserved) = reserved {
+                return Err(CalcError::type_error(format!("cannot assign to the {} `{}`", reserved, variable_name), span));
             }
+            let value: Value = evaluate_ast(*value_node, env)?;
+            env.set(&variable_name, value.clone());
+            Ok(value)
         }
 
-        ASTNode::Variable(variable_name) => match variable_name.as_str() {
-            "pi" => Ok(InterpreterResult::Number(std::f64::consts::PI)),
-            "e" => Ok(InterpreterResult::Number(std::f64::consts::E)),
-            "tau" => Ok(InterpreterResult::Number(std::f64::consts::TAU)),
-            "phi" => Ok(InterpreterResult::Number(1.618033988749895_f64)),
-            _ => Ok(InterpreterResult::Number(0f64))
+        // A variable of the same name shadows the unit, so `f(t) = 2t` still works
+        ASTNodeKind::Unit(name) => match env.get(&name) {
+            Some(value) => Ok(value.clone()),
+            None => Ok(Value::Quantity(Quantity { value: 1f64, unit: Unit::named(&name) })),
+        },
+
+        ASTNodeKind::Variable(variable_name) => {
+            if let Some(value) = env.get(&variable_name) {
+                return Ok(value.clone());
+            }
+            if variable_name == LAST_RESULT {
+                return env.last_result().cloned().ok_or_else(|| CalcError::unknown_identifier("`ans` has no value before the first result", span));
+            }
+            if let Some(number) = history_number(&variable_name) {
+                return env.result(number).cloned()
+                    .ok_or_else(|| CalcError::unknown_identifier(format!("no result `{}` in the history, which has {}", variable_name, env.history().len()), span));
+            }
+            match CONSTANTS.iter().find(|(name, _)| *name == variable_name) {
+                Some((name, value)) => match env.settings().precision {
+                    Some(precision) => Ok(Value::Decimal(decimal::constant(name, precision).unwrap())),
+                    None => Ok(Value::Number(*value)),
+                },
+                None if variable_name == complex::IMAGINARY_UNIT => Ok(Value::Complex(Complex64::i())),
+                None => Err(CalcError::unknown_identifier(format!("unknown variable `{}`", variable_name), span))
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::Engine, error::ErrorKind};
+
+    #[test]
+    fn user_functions() {
+        let mut engine: Engine = Engine::new();
+        assert_eq!(engine.eval("f(x, y) = x^2 + y").unwrap().to_string(), "f(x, y)");
+        engine.eval("g(x) = sqrt(f(x, 0)) + 1").unwrap();
+        assert_eq!(engine.eval("g(3)").unwrap().to_string(), "4");
+        engine.eval("fibo(n) = if(n < 2, n, fibo(n - 1) + fibo(n - 2))").unwrap();
+        assert_eq!(engine.eval("fibo(15)").unwrap().to_string(), "610");
+        assert_eq!(engine.eval("f(1)").unwrap_err().kind, ErrorKind::Arity);
+        assert_eq!(engine.eval("h(1)").unwrap_err().kind, ErrorKind::UnknownIdentifier);
+        assert_eq!(engine.eval("sin(x) = x").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(engine.eval("k(x, x) = x").unwrap_err().kind, ErrorKind::Parse);
+    }
+
+    #[test]
+    fn recursion_is_limited() {
+        let mut engine: Engine = Engine::new();
+        engine.eval("loop(n) = loop(n + 1)").unwrap();
+        assert_eq!(engine.eval("loop(0)").unwrap_err().kind, ErrorKind::Overflow);
+        // The failed call leaves no parameter bindings behind
+        assert_eq!(engine.eval("n").unwrap_err().kind, ErrorKind::UnknownIdentifier);
+    }
+}
@@ -1,18 +1,43 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, fmt};
+
+use num_bigint::BigInt;
+
+use crate::error::CalcError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    // Smallest span covering both self and other
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum Token {
-    Number(f64),
+    // Kept as written so decimal mode can read it without going through f64
+    Number(String),
     Operator(Operator),
     Identifier(String),
     Bool(bool),
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
     Comma,
-    EOF,
+    Assign,
+    Eof,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operator {
     Add,
     Subtract,
@@ -37,88 +62,268 @@ pub enum Operator {
     BWNot,
 }
 
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol: &str = match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Modulo => "%",
+            Operator::Exponent => "^",
+            Operator::NotEqual => "!=",
+            Operator::Equal => "==",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+            Operator::Lesser => "<",
+            Operator::LesserEqual => "<=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Not => "!",
+            Operator::BWLeftShift => "<<",
+            Operator::BWRightShift => ">>",
+            Operator::BWOr => "|",
+            Operator::BWXor => "|^",
+            Operator::BWAnd => "&",
+            Operator::BWNot => "~",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "number `{}`", value),
+            Token::Operator(operator) => write!(f, "`{}`", operator),
+            Token::Identifier(name) => write!(f, "identifier `{}`", name),
+            Token::Bool(value) => write!(f, "`{}`", value),
+            Token::LeftParen => write!(f, "`(`"),
+            Token::RightParen => write!(f, "`)`"),
+            Token::LeftBracket => write!(f, "`[`"),
+            Token::RightBracket => write!(f, "`]`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Assign => write!(f, "`=`"),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
 pub struct Lexer {
-    tokens: VecDeque<Token>,
+    tokens: VecDeque<(Token, Span)>,
+    eof_span: Span,
 }
 
 impl Lexer {
-    pub fn next_token(&mut self) -> Token {
-        self.tokens.pop_back().unwrap_or(Token::EOF)
+    pub fn next_token(&mut self) -> (Token, Span) {
+        self.tokens.pop_back().unwrap_or((Token::Eof, self.eof_span))
     }
 
     pub fn peek(&self) -> Token {
-        self.tokens.back().unwrap_or(&Token::EOF).clone()
+        self.tokens.back().map(|(token, _)| token.clone()).unwrap_or(Token::Eof)
+    }
+
+    // The token `n` places after the next one, `peek_nth(0)` is `peek()`
+    pub fn peek_nth(&self, n: usize) -> Token {
+        self.tokens.iter().rev().nth(n).map(|(token, _)| token.clone()).unwrap_or(Token::Eof)
+    }
+
+    pub fn peek_span(&self) -> Span {
+        self.tokens.back().map(|(_, span)| *span).unwrap_or(self.eof_span)
     }
 
-    pub fn new(text: String) -> Lexer {
-        let mut tokens: VecDeque<Token> = VecDeque::new();
+    pub fn new(text: &str) -> Result<Lexer, CalcError> {
+        let mut tokens: VecDeque<(Token, Span)> = VecDeque::new();
 
-        let mut num_buffer: String = String::new();
-        let mut ide_buffer: String = String::new();
+        let chars: Vec<char> = text.chars().collect();
+        let mut index: usize = 0;
 
-        let mut skip_next: bool = false;
+        while index < chars.len() {
+            let character: char = chars[index];
+            let start: usize = index;
 
-        for (index, character) in text.chars().enumerate() {
-            if skip_next {
-                skip_next = false;
+            if character.is_whitespace() {
+                index += 1;
+                continue;
+            }
+
+            if character == '#' {
+                break; // Comment until the end of the line
+            }
+
+            if character.is_alphabetic() {
+                while index < chars.len() && (chars[index].is_alphanumeric() || chars[index] == '_') {
+                    index += 1;
+                }
+                let identifier: String = chars[start..index].iter().collect();
+                let token: Token = match identifier.as_str() {
+                    "true" => Token::Bool(true),
+                    "false" => Token::Bool(false),
+                    _ => Token::Identifier(identifier),
+                };
+                tokens.push_front((token, Span::new(start, index)));
                 continue;
             }
 
-            if (character.is_alphabetic() && ide_buffer.is_empty()) || (character.is_alphanumeric() && !ide_buffer.is_empty()) {
-                ide_buffer += &character.to_string();
+            // `$2` and `_2` refer to the second result in the history
+            if (character == '$' || character == '_') && chars.get(index + 1).is_some_and(|c| c.is_ascii_digit()) {
+                index += 1;
+                while index < chars.len() && chars[index].is_ascii_digit() {
+                    index += 1;
+                }
+                let identifier: String = chars[start..index].iter().collect();
+                tokens.push_front((Token::Identifier(identifier), Span::new(start, index)));
                 continue;
-            } else if !ide_buffer.is_empty() {
-                match ide_buffer.as_str() {
-                    "true" => tokens.push_front(Token::Bool(true)),
-                    "false" => tokens.push_front(Token::Bool(false)),
-                    _ => tokens.push_front(Token::Identifier(ide_buffer.clone())),
+            }
+
+            let radix: Option<(u32, &str)> = match (character, chars.get(index + 1)) {
+                ('0', Some('x')) | ('0', Some('X')) => Some((16, "hexadecimal")),
+                ('0', Some('b')) | ('0', Some('B')) => Some((2, "binary")),
+                ('0', Some('o')) | ('0', Some('O')) => Some((8, "octal")),
+                _ => None,
+            };
+            if let Some((radix, radix_name)) = radix {
+                index += 2;
+                // Take every alphanumeric so that `0b102` reports the bad digit instead of lexing `2` separately
+                while index < chars.len() && (chars[index].is_alphanumeric() || chars[index] == '_') {
+                    index += 1;
+                }
+                let span: Span = Span::new(start, index);
+                let digits: String = chars[start + 2..index].iter().filter(|c| **c != '_').collect();
+                if let Some(digit) = digits.chars().find(|c| !c.is_digit(radix)) {
+                    return Err(CalcError::lex(format!("invalid digit `{}` in {} literal", digit, radix_name), span));
                 }
-                ide_buffer.clear();
+                if digits.is_empty() {
+                    return Err(CalcError::lex(format!("missing digits in {} literal", radix_name), span));
+                }
+                let value: BigInt = BigInt::parse_bytes(digits.as_bytes(), radix).unwrap();
+                tokens.push_front((Token::Number(value.to_string()), span));
+                continue;
             }
 
-            if character.is_digit(10) || character == '.' {
-                num_buffer += &character.to_string();
+            if character.is_ascii_digit() || character == '.' {
+                while index < chars.len() && (chars[index].is_ascii_digit() || chars[index] == '.') {
+                    index += 1;
+                }
+                // Exponent like `e23` or `E-19`, only if digits follow so `2e` still reads as `2` and `e`
+                if let Some('e') | Some('E') = chars.get(index) {
+                    let sign_length: usize = if let Some('+') | Some('-') = chars.get(index + 1) { 1 } else { 0 };
+                    if chars.get(index + 1 + sign_length).is_some_and(|c| c.is_ascii_digit()) {
+                        index += 1 + sign_length;
+                        while index < chars.len() && chars[index].is_ascii_digit() {
+                            index += 1;
+                        }
+                    }
+                }
+                let mut literal: String = chars[start..index].iter().collect();
+                if literal.parse::<f64>().is_err() {
+                    return Err(CalcError::lex(format!("invalid number literal `{}`", literal), Span::new(start, index)));
+                }
+                // Imaginary suffix like `2i`, but `2in` is still `2` followed by `in`
+                if chars.get(index) == Some(&'i') && !chars.get(index + 1).is_some_and(|c| c.is_alphanumeric()) {
+                    index += 1;
+                    literal.push('i');
+                }
+                let span: Span = Span::new(start, index);
+                tokens.push_front((Token::Number(literal), span));
                 continue;
-            } else if !num_buffer.is_empty() {
-                tokens.push_front(Token::Number(num_buffer.parse::<f64>().expect("Failed to parse String to f64")));
-                num_buffer.clear();
             }
 
-            let next_character = text.chars().nth(index + 1).unwrap_or('\0'); // Just default to a character we ignore
+            let next_character: char = chars.get(index + 1).copied().unwrap_or('\0'); // Just default to a character we ignore
+            let mut length: usize = 1;
 
-            match character {
-                '>' => tokens.push_front(match next_character {
-                    '=' => { skip_next = true; Token::Operator(Operator::GreaterEqual) },
-                    '>' => { skip_next = true; Token::Operator(Operator::BWRightShift) },
+            let token: Token = match character {
+                '>' => match next_character {
+                    '=' => { length = 2; Token::Operator(Operator::GreaterEqual) },
+                    '>' => { length = 2; Token::Operator(Operator::BWRightShift) },
                     _ => Token::Operator(Operator::Greater),
-                }),
-                '<' => tokens.push_front(match next_character {
-                    '=' => { skip_next = true; Token::Operator(Operator::LesserEqual) },
-                    '<' => { skip_next = true; Token::Operator(Operator::BWLeftShift) },
+                },
+                '<' => match next_character {
+                    '=' => { length = 2; Token::Operator(Operator::LesserEqual) },
+                    '<' => { length = 2; Token::Operator(Operator::BWLeftShift) },
                     _ => Token::Operator(Operator::Lesser),
-                }),
-                '|' => tokens.push_front(match next_character {
-                    '|' => { skip_next = true; Token::Operator(Operator::Or) },
-                    '^' => { skip_next = true; Token::Operator(Operator::BWXor) },
+                },
+                '|' => match next_character {
+                    '|' => { length = 2; Token::Operator(Operator::Or) },
+                    '^' => { length = 2; Token::Operator(Operator::BWXor) },
                     _ => Token::Operator(Operator::BWOr),
-                }),
-                '!' => tokens.push_front(if next_character == '=' { skip_next = true; Token::Operator(Operator::NotEqual) } else { Token::Operator(Operator::Not) }),
-                '&' => tokens.push_front(if next_character == '&' { skip_next = true; Token::Operator(Operator::And) } else { Token::Operator(Operator::BWAnd) }),
-                '=' if next_character == '=' => { skip_next = true; tokens.push_front(Token::Operator(Operator::Equal)) },
-                '+' => tokens.push_front(Token::Operator(Operator::Add)),
-                '-' => tokens.push_front(Token::Operator(Operator::Subtract)),
-                '*' => tokens.push_front(Token::Operator(Operator::Multiply)),
-                '/' => tokens.push_front(Token::Operator(Operator::Divide)),
-                '%' => tokens.push_front(Token::Operator(Operator::Modulo)),
-                '^' => tokens.push_front(Token::Operator(Operator::Exponent)),
-                '~' => tokens.push_front(Token::Operator(Operator::BWNot)),
-                '(' => tokens.push_front(Token::LeftParen),
-                ')' => tokens.push_front(Token::RightParen),
-                ',' => tokens.push_front(Token::Comma),
-                _ => (),
+                },
+                '!' => if next_character == '=' { length = 2; Token::Operator(Operator::NotEqual) } else { Token::Operator(Operator::Not) },
+                '&' => if next_character == '&' { length = 2; Token::Operator(Operator::And) } else { Token::Operator(Operator::BWAnd) },
+                '=' => if next_character == '=' { length = 2; Token::Operator(Operator::Equal) } else { Token::Assign },
+                '+' => Token::Operator(Operator::Add),
+                '-' => Token::Operator(Operator::Subtract),
+                '*' => Token::Operator(Operator::Multiply),
+                '/' => Token::Operator(Operator::Divide),
+                '%' => Token::Operator(Operator::Modulo),
+                '^' => Token::Operator(Operator::Exponent),
+                '~' => Token::Operator(Operator::BWNot),
+                '(' => Token::LeftParen,
+                ')' => Token::RightParen,
+                '[' => Token::LeftBracket,
+                ']' => Token::RightBracket,
+                ',' => Token::Comma,
+                _ => return Err(CalcError::lex(format!("unexpected character `{}`", character), Span::new(start, start + 1))),
+            };
+
+            index += length;
+            tokens.push_front((token, Span::new(start, index)));
+        }
+
+        let eof_span: Span = Span::new(chars.len(), chars.len() + 1);
+        Ok(Lexer { tokens, eof_span })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn tokens(text: &str) -> Vec<Token> {
+        let mut lexer: Lexer = Lexer::new(text).unwrap();
+        let mut tokens: Vec<Token> = Vec::new();
+        loop {
+            match lexer.next_token().0 {
+                Token::Eof => return tokens,
+                token => tokens.push(token),
             }
         }
+    }
+
+    fn number(text: &str) -> String {
+        match tokens(text).as_slice() {
+            [Token::Number(number)] => number.clone(),
+            tokens => panic!("expected a single number for `{}`, got {:?}", text, tokens),
+        }
+    }
+
+    #[test]
+    fn radix_literals() {
+        assert_eq!(number("0xFF"), "255");
+        assert_eq!(number("0b1010"), "10");
+        assert_eq!(number("0o17"), "15");
+        assert_eq!(number("0xFFFF_FFFF"), "4294967295");
+        assert_eq!(number("0x1FFFFFFFFFFFFFFFF"), "36893488147419103231");
+    }
+
+    #[test]
+    fn invalid_radix_literals() {
+        assert_eq!(Lexer::new("0b102").err().unwrap().kind, ErrorKind::Lex);
+        assert_eq!(Lexer::new("0x").err().unwrap().kind, ErrorKind::Lex);
+    }
+
+    #[test]
+    fn scientific_literals() {
+        assert_eq!(number("6.022e23"), "6.022e23");
+        assert_eq!(number("1.6E-19"), "1.6E-19");
+        assert_eq!(number("2e+3"), "2e+3");
+    }
 
-        Lexer { tokens }
+    #[test]
+    fn exponent_needs_digits() {
+        assert!(matches!(tokens("2e").as_slice(), [Token::Number(n), Token::Identifier(e)] if n == "2" && e == "e"));
+        assert!(matches!(tokens("2in").as_slice(), [Token::Number(n), Token::Identifier(unit)] if n == "2" && unit == "in"));
+        assert_eq!(number("2i"), "2i");
     }
 }
@@ -0,0 +1,48 @@
+//! A small expression calculator.
+//!
+//! The quickest way in is [`eval`], which evaluates a single expression:
+//!
+//! ```
+//! assert_eq!(cli_calc::eval("2^10 + 1").unwrap().to_string(), "1025");
+//! assert_eq!(cli_calc::eval("1/3 + 1/6").unwrap().to_string(), "1/2");
+//! ```
+//!
+//! Errors carry an [`ErrorKind`] and the [`Span`] of the offending input, so
+//! hosts can tell a syntax error from a type or domain error.
+
+pub(crate) mod angle;
+pub(crate) mod builtins;
+pub(crate) mod calculus;
+pub(crate) mod complex;
+pub(crate) mod decimal;
+pub(crate) mod engine;
+pub(crate) mod environment;
+pub(crate) mod error;
+pub(crate) mod format;
+pub(crate) mod functions;
+pub(crate) mod integer;
+pub(crate) mod interpreter;
+pub(crate) mod lexer;
+pub(crate) mod numeric;
+pub(crate) mod parser;
+pub(crate) mod rational;
+pub(crate) mod stats;
+pub(crate) mod symbolic;
+#[cfg(test)]
+mod testing;
+pub(crate) mod units;
+pub(crate) mod vector;
+
+pub use angle::AngleMode;
+pub use engine::Engine;
+pub use environment::HistoryEntry;
+pub use error::{CalcError, ErrorKind};
+pub use format::Format;
+pub use functions::{Arity, CallContext, Function};
+pub use interpreter::Value;
+pub use lexer::Span;
+
+/// Evaluates a single expression with a fresh [`Engine`].
+pub fn eval(input: &str) -> Result<Value, CalcError> {
+    Engine::new().eval(input)
+}
@@ -1,121 +1,425 @@
-mod interpreter;
-mod lexer;
-mod parser;
+mod completion;
 
-use interpreter::*;
-use lexer::*;
-use parser::*;
+use cli_calc::{AngleMode, CalcError, Engine, Format, Span};
 
 use std::{
-    io::{self, Write},
+    env,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, IsTerminal},
+    path::PathBuf,
     process,
 };
 
-use colour::yellow;
+use colour::{e_red, e_red_ln};
+use completion::CalcHelper;
+use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
 
-fn main() {
-    println!("cli-calc version 1.1\ntype :help for commands");
+const USAGE: &str = "\
+usage: cli-calc                  start the interactive calculator, or read
+                                 one expression per line from piped stdin
+       cli-calc <expression>...  evaluate an expression and print the result
+       cli-calc -e <expression>  same, may be repeated
+       cli-calc <file>.calc      evaluate a script, one expression per line
 
-    let mut debug: bool = false;
+options:
+  -e, --expr <expression>  evaluate an expression
+  -h, --help               print this message
 
-    loop {
-        yellow!(">> "); // print!() yellow
-        io::stdout().flush().unwrap();
+Expressions and scripts run in the order given and share variables.";
 
-        let mut input: String = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read input");
+// A command line argument to evaluate
+enum Input {
+    Expression(String),
+    Script(String),
+}
 
-        match input.trim() {
+struct Session {
+    engine: Engine,
+    debug: bool,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session { engine: Engine::new(), debug: false }
+    }
+
+    fn evaluate(&mut self, line: &str) -> Result<String, CalcError> {
+        if self.debug {
+            println!("{}", self.engine.syntax_tree(line)?);
+        }
+        self.engine.eval_to_string(line)
+    }
+
+    // Runs a `:command` or evaluates and prints an expression, returns false on `:exit`
+    fn run_line(&mut self, line: &str) -> Result<bool, CalcError> {
+        let (command, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        match command {
+            "" => (),
             ":debug" => {
-                debug = !debug;
-                println!("debug = {}", debug);
+                self.debug = !self.debug;
+                println!("debug = {}", self.debug);
             },
-            ":exit" => process::exit(0),
+            ":exit" => return Ok(false),
+            ":format" => set_format(&mut self.engine, argument),
+            ":precision" => set_precision(&mut self.engine, argument),
+            ":angle" => set_angle_mode(&mut self.engine, argument),
+            ":history" => {
+                for (index, entry) in self.engine.history().iter().enumerate() {
+                    println!("${}: {} = {}", index + 1, entry.input, self.engine.format_value(&entry.value));
+                }
+            }
             ":help" => {
                 println!("\
                     :help\n\
                     :opers\n\
                     :funcs\n\
+                    :format [dec|hex|bin|oct|sci|eng|fixed N|sep]\n\
+                    :precision [N|off]\n\
+                    :angle [rad|deg|grad]\n\
+                    :history\n\
                     :debug\n\
                     :exit\
                 ");
             }
             ":opers" => {
                 println!("\
-                    1: ( )\t\t\t[Parentheses]\n\
+                    1: ( ) [ ] v[i]\t\t[Parentheses, Lists, Indexing]\n\
                     2: ^\t\t\t[Exponentiation]\n\
                     3: + - ~ !\t\t[Unary]\n\
-                    4: * / %\t\t[Multiply, Divide, Modulo]\n\
-                    5: << >>\t\t[Bitwise Shifts]\n\
-                    6: | & |^\t\t[Bitwise Or, And, Xor]\n\
-                    7: + -\t\t\t[Add, Subtract]\n\
-                    8: == != > >= < <=\t[Comparisons]\n\
-                    9: || &&\t\t[Or, And]\
+                    4: 2x  5 km\t\t[Implicit Multiply by a Name]\n\
+                    5: * / %\t\t[Multiply, Divide, Modulo]\n\
+                    6: << >>\t\t[Bitwise Shifts]\n\
+                    7: | & |^\t\t[Bitwise Or, And, Xor]\n\
+                    8: + -\t\t\t[Add, Subtract]\n\
+                    9: == != > >= < <=\t[Comparisons]\n\
+                    10: || &&\t\t[Or, And]\n\
+                    11: to in\t\t[Format or Convert, e.g. `to hex`, `in km/h`]\n\
+                    12: =\t\t\t[Assignment, e.g. `x = 2`, `f(x) = x^2`]\n\
+                    \n\
+                    Literals: 42  3.14  1.6e-19  0xFF  0b1010  0o17  0xFFFF_FFFF  2i  true  false\n\
+                    Results:  ans  $1  _1\
                 ");
             }
             ":funcs" => {
-                println!("\
-                    abs(x)\n\
-                    acos(x)\n\
-                    acosh(x)\n\
-                    asin(x)\n\
-                    asinh(x)\n\
-                    atan(x)\n\
-                    atan2(x, y)\n\
-                    atanh(x)\n\
-                    ceil(x)\n\
-                    ncbrt(x)\n\
-                    cos(x)\n\
-                    cosh(x)\n\
-                    deg(x)\n\
-                    exp(x)\n\
-                    fact(x)\n\
-                    floor(x)\n\
-                    fract(x)\n\
-                    hypot(x, y)\n\
-                    ln(x)\n\
-                    log10(x)\n\
-                    log2(x)\n\
-                    log(base, x)\n\
-                    max(x, y)\n\
-                    min(x, y)\n\
-                    pow(x, exponent)\n\
-                    rad(x)\n\
-                    round(x)\n\
-                    sign(x)\n\
-                    sin(x)\n\
-                    sinh(x)\n\
-                    sqrt(x)\n\
-                    sum(...)\n\
-                    rad(x)\n\
-                    root(root, x)\n\
-                    tan(x)\n\
-                    tanh(x)\n\
-                    trunc(x)\
-                ");
+                for signature in self.engine.function_signatures() {
+                    println!("{}", signature);
+                }
             }
+            _ if command.starts_with('#') => (),
+            _ => println!("{}", self.evaluate(line)?),
+        }
+        Ok(true)
+    }
+}
+
+// `:format`, `:format hex`, `:format fixed 4`
+fn set_format(engine: &mut Engine, argument: &str) {
+    let mut words = argument.split_whitespace();
+    let name: &str = match words.next() {
+        Some(name) => name,
+        None => {
+            println!("format = {}", engine.format());
+            return;
+        }
+    };
+    let digits: Option<usize> = match words.next().map(|word| word.parse::<usize>()) {
+        Some(Ok(digits)) if digits <= Format::MAX_DIGITS => Some(digits),
+        Some(Ok(_)) => {
+            println!("expected at most {} digits", Format::MAX_DIGITS);
+            return;
+        }
+        Some(Err(_)) => {
+            println!("expected a whole number of digits");
+            return;
+        }
+        None => None,
+    };
+    match Format::from_name(name, digits) {
+        Some(format) => {
+            engine.set_format(format);
+            println!("format = {}", format);
+        }
+        None => println!("unknown format `{}`, expected one of: {}", argument, Format::NAMES.join(", ")),
+    }
+}
+
+// `:precision`, `:precision 50`, `:precision off`
+fn set_precision(engine: &mut Engine, argument: &str) {
+    match argument.trim() {
+        "" => (),
+        "off" => engine.set_precision(None),
+        digits => match digits.parse::<u64>() {
+            Ok(digits) if (1..=Engine::MAX_PRECISION).contains(&digits) => engine.set_precision(Some(digits)),
             _ => {
-                let lexer: Lexer = Lexer::new(input);
-                let mut parser: Parser = Parser::new(lexer);
-                match parser.parse_from_top() {
-                    Ok(n) => {
-                        if debug { println!("{:?}", n); }
-                        let result = evaluate_ast(n);
-                        match result {
-                            Ok(r) => {
-                                match r {
-                                    InterpreterResult::Number(value) => println!("{}", value),
-                                    InterpreterResult::Bool(value) => println!("{}", value)
-                                }
-                            }
-                            Err(m) => println!("{}", m)
-                        }
-                    },
-                    Err(m) => println!("{}", m)
+                println!("expected `off` or a number of digits from 1 to {}", Engine::MAX_PRECISION);
+                return;
+            }
+        },
+    }
+    match engine.precision() {
+        Some(digits) => println!("precision = {} digits", digits),
+        None => println!("precision = off"),
+    }
+}
+
+// `:angle`, `:angle deg`
+fn set_angle_mode(engine: &mut Engine, argument: &str) {
+    match argument.trim() {
+        "" => (),
+        name => match AngleMode::from_name(name) {
+            Some(mode) => engine.set_angle_mode(mode),
+            None => {
+                println!("unknown angle mode `{}`, expected one of: {}", name, AngleMode::NAMES.join(", "));
+                return;
+            }
+        },
+    }
+    println!("angle = {}", engine.angle_mode());
+}
+
+// Marks the span under a line of input, e.g. `    ^~` under `2 + ** 3`
+fn underline(line: &str, span: Span) -> (String, String) {
+    let width: usize = line.chars().count();
+    let start: usize = span.start.min(width);
+    let length: usize = span.end.saturating_sub(span.start).max(1);
+    (" ".repeat(start), format!("^{}", "~".repeat(length - 1)))
+}
+
+// Echo the offending line and underline the span, e.g.
+//   2 + * 3
+//       ^
+fn report_error(line: &str, error: &CalcError) {
+    let (indent, underline) = underline(line, error.span);
+    eprintln!("{}", line);
+    eprint!("{}", indent);
+    // Keep escape codes out of redirected output
+    if io::stderr().is_terminal() {
+        e_red_ln!("{}", underline);
+        e_red!("{}: ", error.kind);
+    } else {
+        eprintln!("{}", underline);
+        eprint!("{}: ", error.kind);
+    }
+    eprintln!("{}", error.message);
+}
+
+// Evaluates expressions given on the command line, returning the exit code
+fn run_arguments(args: &[String]) -> i32 {
+    let mut inputs: Vec<Input> = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    // Bare words form one expression, placed where the first of them appeared
+    let mut words_at: usize = 0;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return 0;
+            }
+            "-e" | "--expr" => match args.next() {
+                Some(expression) => inputs.push(Input::Expression(expression.clone())),
+                None => {
+                    eprintln!("{} requires an expression\n{}", arg, USAGE);
+                    return 2;
+                }
+            },
+            // Everything after `--` is part of the expression, even if it looks like a flag
+            "--" => {
+                if words.is_empty() {
+                    words_at = inputs.len();
                 }
+                words.extend(args.by_ref().cloned());
             }
+            _ if arg.ends_with(".calc") => inputs.push(Input::Script(arg.clone())),
+            _ => match arg.strip_prefix("--expr=") {
+                Some(expression) => inputs.push(Input::Expression(expression.to_string())),
+                None => {
+                    if words.is_empty() {
+                        words_at = inputs.len();
+                    }
+                    words.push(arg.clone());
+                }
+            },
         }
     }
+    if !words.is_empty() {
+        inputs.insert(words_at, Input::Expression(words.join(" ")));
+    }
+
+    let mut session: Session = Session::new();
+    let mut status: i32 = 0;
+    for input in inputs.iter() {
+        match input {
+            Input::Script(script) => match File::open(script) {
+                Ok(file) => status = status.max(run_batch(&mut session, BufReader::new(file), script)),
+                Err(e) => {
+                    eprintln!("{}: {}", script, e);
+                    return 1;
+                }
+            },
+            Input::Expression(expression) => {
+                if let Err(e) = session.evaluate(expression).map(|result| println!("{}", result)) {
+                    report_error(expression, &e);
+                    return 1;
+                }
+            }
+        }
+    }
+    status
+}
+
+// Evaluates one line at a time, printing one result per line; returns the exit code
+fn run_batch(session: &mut Session, reader: impl BufRead, source: &str) -> i32 {
+    let mut status: i32 = 0;
+    for (number, line) in reader.lines().enumerate() {
+        let line: String = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("{}: {}", source, e);
+                return 1;
+            }
+        };
+        match session.run_line(&line) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => {
+                eprintln!("{}:{}:", source, number + 1);
+                report_error(&line, &e);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+fn history_path() -> Option<PathBuf> {
+    let directory: PathBuf = dirs::config_dir()?.join("cli-calc");
+    fs::create_dir_all(&directory).ok()?;
+    Some(directory.join("history"))
+}
+
+fn run_repl(session: &mut Session) -> rustyline::Result<()> {
+    let mut editor: Editor<CalcHelper, DefaultHistory> = Editor::new()?;
+    let mut helper: CalcHelper = CalcHelper::default();
+    helper.update(&session.engine);
+    editor.set_helper(Some(helper));
+
+    let history: Option<PathBuf> = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path); // Missing on first run
+    }
+
+    println!("cli-calc version 1.1\ntype :help for commands");
+
+    loop {
+        let line: String = match editor.readline(">> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue, // Ctrl-C discards the current line
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+
+        match session.run_line(&line) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) => report_error(&line, &e),
+        }
+        if let Some(helper) = editor.helper_mut() {
+            helper.update(&session.engine);
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        process::exit(run_arguments(&args));
+    }
+
+    let mut session: Session = Session::new();
+    if io::stdin().is_terminal() {
+        if let Err(e) = run_repl(&mut session) {
+            eprintln!("Failed to read input: {}", e);
+            process::exit(1);
+        }
+    } else {
+        let stdin = io::stdin();
+        process::exit(run_batch(&mut session, stdin.lock(), "<stdin>"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caret(line: &str) -> String {
+        let error: CalcError = Engine::new().eval(line).unwrap_err();
+        let (indent, underline) = underline(line, error.span);
+        format!("{}{}", indent, underline)
+    }
+
+    #[test]
+    fn underlines_error_spans() {
+        assert_eq!(caret("2 + * 3"), "    ^");
+        assert_eq!(caret("1 + foo"), "    ^~~");
+        assert_eq!(caret("max(1, 2"), "        ^");
+        assert_eq!(caret("2 @ 3"), "  ^");
+        assert_eq!(caret("sqrt(1, 2)"), "^~~~~~~~~~");
+    }
+
+    fn arguments(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(run_arguments(&arguments(&["2^10", "+", "1"])), 0);
+        assert_eq!(run_arguments(&arguments(&["-e", "x = 3", "--expr=x * 2", "--", "-x"])), 0);
+        assert_eq!(run_arguments(&arguments(&["--help"])), 0);
+        assert_eq!(run_arguments(&arguments(&["-e", "1 +"])), 1);
+        assert_eq!(run_arguments(&arguments(&["-e", "1", "-e", "y"])), 1);
+        assert_eq!(run_arguments(&arguments(&["missing.calc"])), 1);
+        assert_eq!(run_arguments(&arguments(&["-e"])), 2);
+    }
+
+    fn batch(text: &str) -> (i32, Session) {
+        let mut session: Session = Session::new();
+        let status: i32 = run_batch(&mut session, io::Cursor::new(text), "<test>");
+        (status, session)
+    }
+
+    #[test]
+    fn batch_mode() {
+        let (status, mut session) = batch("# prices\n\nx = 2\n  \ny = x * 3 # tripled\n");
+        assert_eq!(status, 0);
+        assert_eq!(session.engine.eval("y").unwrap().to_string(), "6");
+        // A failing line sets the exit code without stopping the rest
+        let (status, mut session) = batch("x = 1\nx +\nx = 5\n");
+        assert_eq!(status, 1);
+        assert_eq!(session.engine.eval("x").unwrap().to_string(), "5");
+        let (status, mut session) = batch("x = 1\n:exit\nx = 5\n");
+        assert_eq!(status, 0);
+        assert_eq!(session.engine.eval("x").unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn runs_scripts() {
+        let path: PathBuf = env::temp_dir().join(format!("cli-calc-test-{}.calc", process::id()));
+        fs::write(&path, "rate = 4\nrate * 2\n").unwrap();
+        let script: String = path.to_string_lossy().into_owned();
+        assert_eq!(run_arguments(&arguments(&[&script, "-e", "rate + 1"])), 0);
+        assert_eq!(run_arguments(&arguments(&["-e", "rate + 1", &script])), 1);
+        fs::write(&path, "1 +\n2\n").unwrap();
+        assert_eq!(run_arguments(&arguments(&[&script])), 1);
+        fs::remove_file(&path).unwrap();
+    }
 }
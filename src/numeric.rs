@@ -0,0 +1,209 @@
+use std::cmp::Ordering;
+
+use bigdecimal::{BigDecimal, Signed};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+
+use crate::{complex, decimal, environment::Settings, error::CalcError, integer, interpreter::Value, lexer::*, rational, units, vector};
+
+/// Turns a number literal into a value: a decimal when decimal mode is on,
+/// otherwise an exact integer or a float depending on how it's written.
+pub fn parse_literal(text: &str, settings: &Settings, span: Span) -> Result<Value, CalcError> {
+    // Imaginary literals like `2i` are always floats, there is no exact complex type
+    if let Some(imaginary) = text.strip_suffix('i') {
+        return match parse_literal(imaginary, &Settings::default(), span).map(|value| to_float(&value)) {
+            Ok(Some(value)) => Ok(complex::normalize(Complex64::new(0f64, value))),
+            _ => Err(CalcError::overflow(format!("number literal `{}` is too large", text), span)),
+        };
+    }
+    if let Some(precision) = settings.precision {
+        return Ok(Value::Decimal(decimal::parse(text, precision, span)?));
+    }
+    if text.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(Value::Integer(integer::parse(text)));
+    }
+    match text.parse::<f64>() {
+        Ok(value) if value.is_infinite() => Err(CalcError::overflow(format!("number literal `{}` is too large", text), span)),
+        Ok(value) => Ok(Value::Number(value)),
+        Err(_) => Err(CalcError::lex(format!("invalid number literal `{}`", text), span)),
+    }
+}
+
+pub fn to_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(value) => Some(*value),
+        Value::Integer(value) => Some(value.to_f64().unwrap_or(f64::NAN)),
+        Value::Rational(value) => Some(rational::to_f64(value)),
+        Value::Decimal(value) => Some(decimal::to_f64(value)),
+        _ => None,
+    }
+}
+
+// Integers and fractions, the values that do exact arithmetic together
+fn to_rational(value: &Value) -> Option<BigRational> {
+    match value {
+        Value::Integer(value) => Some(BigRational::from_integer(value.clone())),
+        Value::Rational(value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+// Exact value of any number except infinity and NaN
+fn to_exact(value: &Value) -> Option<BigRational> {
+    match value {
+        Value::Number(value) => rational::from_f64(*value),
+        Value::Decimal(value) => Some(decimal::to_rational(value)),
+        _ => to_rational(value),
+    }
+}
+
+// A finite power too large for a float is an error rather than `inf`
+pub fn float_power(base: f64, exponent: f64, span: Span) -> Result<f64, CalcError> {
+    let result: f64 = base.powf(exponent);
+    if result.is_infinite() && base.is_finite() && exponent.is_finite() && base != 0f64 {
+        return Err(CalcError::overflow(format!("{}^{} is too large to represent", base, exponent), span));
+    }
+    Ok(result)
+}
+
+pub fn float_operation(operator: &Operator, a: f64, b: f64, span: Span) -> Result<f64, CalcError> {
+    Ok(match operator {
+        Operator::Add => a + b,
+        Operator::Subtract => a - b,
+        Operator::Multiply => a * b,
+        Operator::Divide => a / b,
+        Operator::Modulo => a % b,
+        Operator::Exponent => float_power(a, b, span)?,
+        _ => return Err(CalcError::parse("Invalid binary operator", span)),
+    })
+}
+
+fn to_decimal(value: Value, precision: u64, span: Span) -> Result<BigDecimal, CalcError> {
+    match value {
+        Value::Decimal(value) => Ok(value),
+        Value::Integer(value) => Ok(decimal::round(BigDecimal::from(value), precision)),
+        Value::Rational(value) => Ok(decimal::from_rational(&value, precision)),
+        Value::Number(value) => decimal::from_f64(value, span),
+        _ => Err(CalcError::type_error("Attempt to perform arithmetic/bitwise operators on non-numbers", span)),
+    }
+}
+
+// Mixed operands take the less exact type of the two: integer, then rational, then float, then decimal
+pub fn binary_operation(operator: &Operator, left: Value, right: Value, span: Span, settings: &Settings) -> Result<Value, CalcError> {
+    if matches!(left, Value::List(_)) || matches!(right, Value::List(_)) {
+        return vector::operation(operator, &left, &right, span, settings);
+    }
+    if matches!(left, Value::Quantity(_)) || matches!(right, Value::Quantity(_)) {
+        return units::operation(operator, &left, &right, span);
+    }
+    if matches!(left, Value::Complex(_)) || matches!(right, Value::Complex(_)) {
+        return complex_operation(operator, &left, &right, span);
+    }
+    let result: Value = real_operation(operator, left.clone(), right.clone(), span, settings)?;
+    // A negative base to a fractional power, like `(-8)^(1/3)`, has only complex roots
+    let is_nan = |value: &Value| matches!(value, Value::Number(value) if value.is_nan());
+    if is_nan(&result) && !is_nan(&left) && !is_nan(&right) && matches!(operator, Operator::Exponent) {
+        return complex_operation(operator, &left, &right, span);
+    }
+    Ok(result)
+}
+
+fn complex_operation(operator: &Operator, left: &Value, right: &Value, span: Span) -> Result<Value, CalcError> {
+    match (complex::to_complex(left), complex::to_complex(right)) {
+        (Some(a), Some(b)) => complex::operation(operator, a, b, span),
+        _ => Err(CalcError::type_error("Attempt to perform arithmetic/bitwise operators on non-numbers", span)),
+    }
+}
+
+fn real_operation(operator: &Operator, left: Value, right: Value, span: Span, settings: &Settings) -> Result<Value, CalcError> {
+    // Bitwise operators work on exact integers whatever the representation, fractions are an error
+    if integer::is_bitwise(operator) {
+        let name: String = format!("`{}`", operator);
+        let (a, b) = (integer::to_integer(&left, &name, span)?, integer::to_integer(&right, &name, span)?);
+        return Ok(Value::Integer(integer::bitwise_operation(operator, a, b, span)?));
+    }
+    if let (Some(a), Some(b)) = (to_rational(&left), to_rational(&right)) {
+        return rational::operation(operator, a, b, span);
+    }
+    let has_decimal: bool = matches!(left, Value::Decimal(_)) || matches!(right, Value::Decimal(_));
+    match (left, right) {
+        (left, right) if has_decimal => {
+            let precision: u64 = settings.decimal_precision();
+            let (a, b) = (to_decimal(left, precision, span)?, to_decimal(right, precision, span)?);
+            // As with floats, a negative base to a fractional power continues in the complex plane
+            if matches!(operator, Operator::Exponent) && a.is_negative() && !b.is_integer() {
+                return complex_operation(operator, &Value::Decimal(a), &Value::Decimal(b), span);
+            }
+            Ok(Value::Decimal(decimal::operation(operator, a, b, precision, span)?))
+        }
+        (left, right) => match (to_float(&left), to_float(&right)) {
+            (Some(a), Some(b)) => Ok(Value::Number(float_operation(operator, a, b, span)?)),
+            _ => Err(CalcError::type_error("Attempt to perform arithmetic/bitwise operators on non-numbers", span)),
+        },
+    }
+}
+
+pub fn negate(value: Value, span: Span) -> Result<Value, CalcError> {
+    match value {
+        Value::Number(value) => Ok(Value::Number(-value)),
+        Value::Integer(value) => Ok(Value::Integer(-value)),
+        Value::Rational(value) => Ok(Value::Rational(-value)),
+        Value::Decimal(value) => Ok(Value::Decimal(-value)),
+        Value::Complex(value) => Ok(Value::Complex(-value)),
+        Value::Quantity(value) => Ok(units::negate(value)),
+        Value::List(items) => vector::map(items, span, negate),
+        _ => Err(CalcError::type_error("Attempt to perform arithmetic negation on non-number", span)),
+    }
+}
+
+pub fn bitwise_not(value: Value, span: Span) -> Result<Value, CalcError> {
+    match value {
+        Value::Complex(_) => Err(CalcError::type_error("`~` is not defined for complex numbers", span)),
+        Value::Quantity(_) => Err(CalcError::type_error("`~` is not defined for quantities with units", span)),
+        Value::List(items) => vector::map(items, span, bitwise_not),
+        value @ (Value::Number(_) | Value::Integer(_) | Value::Rational(_) | Value::Decimal(_)) => Ok(Value::Integer(!integer::to_integer(&value, "`~`", span)?)),
+        _ => Err(CalcError::type_error("Attempt to perform bitwise not on non-number", span)),
+    }
+}
+
+// `<` and friends need an actual ordering, unlike `==` which is just false for values of different kinds
+pub fn order(operator: &Operator, left: &Value, right: &Value, span: Span) -> Result<Option<Ordering>, CalcError> {
+    let is_nan = |value: &Value| matches!(value, Value::Number(value) if value.is_nan());
+    let unordered: bool = [left, right].iter().any(|value| matches!(value, Value::Complex(_) | Value::List(_)));
+    match compare(left, right) {
+        None if is_nan(left) || is_nan(right) => Ok(None),
+        ordering @ Some(_) if !unordered => Ok(ordering),
+        _ => Err(CalcError::type_error(format!("`{}` cannot order {} and {}", operator, left, right), span)),
+    }
+}
+
+// Numbers compare by value whatever their representation, so `0.5 == 1/2` holds in every mode
+pub fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    if matches!(left, Value::List(_)) || matches!(right, Value::List(_)) {
+        return vector::compare(left, right);
+    }
+    if matches!(left, Value::Quantity(_)) || matches!(right, Value::Quantity(_)) {
+        return units::compare(left, right);
+    }
+    // Complex numbers can only be equal or not
+    if matches!(left, Value::Complex(_)) || matches!(right, Value::Complex(_)) {
+        return match (complex::to_complex(left), complex::to_complex(right)) {
+            (Some(a), Some(b)) if a == b => Some(Ordering::Equal),
+            _ => None,
+        };
+    }
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
+        _ => match (to_exact(left), to_exact(right)) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            // Infinity and NaN have no exact value
+            _ => match (to_float(left), to_float(right)) {
+                (Some(a), Some(b)) => a.partial_cmp(&b),
+                _ if left == right => Some(Ordering::Equal),
+                _ => None,
+            },
+        },
+    }
+}
@@ -1,14 +1,123 @@
-use crate::lexer::*;
+use std::fmt;
 
-#[derive(Debug, Clone)]
-pub enum ASTNode {
-    Number(f64),
+use crate::{error::CalcError, format::*, lexer::*, units::{self, Unit}};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ASTNodeKind {
+    Number(String),
     Bool(bool),
     Variable(String),
+    // A unit name read after a quantity, e.g. the `km` and `h` of `5 km/h`
+    Unit(String),
     Function(String, Vec<ASTNode>),
     Unary(Operator, Box<ASTNode>),
     Binary(Operator, Box<ASTNode>, Box<ASTNode>),
-    Comparison(Vec<Operator>, Vec<ASTNode>)
+    Comparison(Vec<Operator>, Vec<ASTNode>),
+    Assign(String, Box<ASTNode>),
+    FunctionDef(String, Vec<String>, Box<ASTNode>),
+    Format(Box<ASTNode>, Format),
+    Convert(Box<ASTNode>, Unit),
+    List(Vec<ASTNode>),
+    Index(Box<ASTNode>, Box<ASTNode>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ASTNode {
+    pub kind: ASTNodeKind,
+    pub span: Span,
+}
+
+impl ASTNode {
+    pub fn new(kind: ASTNodeKind, span: Span) -> ASTNode {
+        ASTNode { kind, span }
+    }
+
+    fn binary(operator: Operator, left: ASTNode, right: ASTNode) -> ASTNode {
+        let span: Span = left.span.to(right.span);
+        ASTNode::new(ASTNodeKind::Binary(operator, Box::new(left), Box::new(right)), span)
+    }
+}
+
+// How tightly each kind of node binds, matching the parse_* functions below
+fn precedence(kind: &ASTNodeKind) -> u8 {
+    match kind {
+        ASTNodeKind::Assign(..) | ASTNodeKind::FunctionDef(..) | ASTNodeKind::Format(..) | ASTNodeKind::Convert(..) => 0,
+        ASTNodeKind::Binary(Operator::And | Operator::Or, ..) => 1,
+        ASTNodeKind::Comparison(..) => 2,
+        ASTNodeKind::Binary(Operator::Add | Operator::Subtract, ..) => 3,
+        ASTNodeKind::Binary(Operator::BWOr | Operator::BWAnd | Operator::BWXor, ..) => 4,
+        ASTNodeKind::Binary(Operator::BWLeftShift | Operator::BWRightShift, ..) => 5,
+        ASTNodeKind::Binary(Operator::Exponent, ..) => 8,
+        ASTNodeKind::Binary(..) => 6,
+        ASTNodeKind::Unary(..) => 7,
+        _ => 9,
+    }
+}
+
+impl ASTNode {
+    // Wraps self in parentheses when it binds looser than its position allows
+    fn fmt_operand(&self, f: &mut fmt::Formatter, minimum: u8) -> fmt::Result {
+        if precedence(&self.kind) < minimum {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+// Infix text that parses back to the same tree, e.g. `2*x*sin(x) + x^2*cos(x)`
+impl fmt::Display for ASTNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |nodes: &[ASTNode]| nodes.iter().map(|node| node.to_string()).collect::<Vec<String>>().join(", ");
+        let own: u8 = precedence(&self.kind);
+        match &self.kind {
+            ASTNodeKind::Number(literal) => write!(f, "{}", literal),
+            ASTNodeKind::Bool(value) => write!(f, "{}", value),
+            ASTNodeKind::Variable(name) | ASTNodeKind::Unit(name) => write!(f, "{}", name),
+            ASTNodeKind::Function(name, args) => write!(f, "{}({})", name, join(args)),
+            ASTNodeKind::List(items) => write!(f, "[{}]", join(items)),
+            ASTNodeKind::Index(list, index) => {
+                list.fmt_operand(f, 9)?;
+                write!(f, "[{}]", index)
+            }
+            ASTNodeKind::Unary(operator, operand) => {
+                write!(f, "{}", operator)?;
+                operand.fmt_operand(f, own)
+            }
+            // `^` groups to the right, everything else to the left
+            ASTNodeKind::Binary(Operator::Exponent, base, exponent) => {
+                base.fmt_operand(f, own + 1)?;
+                write!(f, "^")?;
+                exponent.fmt_operand(f, own)
+            }
+            ASTNodeKind::Binary(operator, left, right) => {
+                left.fmt_operand(f, own)?;
+                match operator {
+                    // `2 m` has to stay juxtaposed to read back as a unit
+                    Operator::Multiply if matches!(right.kind, ASTNodeKind::Unit(_)) => write!(f, " ")?,
+                    Operator::Multiply | Operator::Divide => write!(f, "{}", operator)?,
+                    operator => write!(f, " {} ", operator)?,
+                }
+                right.fmt_operand(f, own + 1)
+            }
+            ASTNodeKind::Comparison(operators, operands) => {
+                operands[0].fmt_operand(f, own + 1)?;
+                for (operator, operand) in operators.iter().zip(operands[1..].iter()) {
+                    write!(f, " {} ", operator)?;
+                    operand.fmt_operand(f, own + 1)?;
+                }
+                Ok(())
+            }
+            ASTNodeKind::Assign(name, value) => write!(f, "{} = {}", name, value),
+            ASTNodeKind::FunctionDef(name, params, body) => write!(f, "{}({}) = {}", name, params.join(", "), body),
+            ASTNodeKind::Format(inner, format) => write!(f, "{} to {}", inner, format),
+            ASTNodeKind::Convert(inner, unit) => write!(f, "{} to {}", inner, unit),
+        }
+    }
+}
+
+fn is_conversion_keyword(word: &str) -> bool {
+    word == "to" || word == "in"
 }
 
 pub struct Parser {
@@ -16,13 +125,13 @@ pub struct Parser {
 }
 
 impl Parser {
-    fn parse_and_or(&mut self) -> Result<ASTNode, String> {
+    fn parse_and_or(&mut self) -> Result<ASTNode, CalcError> {
         let mut comp: ASTNode = self.parse_comparison()?;
         while let Token::Operator(peek) = self.lexer.peek() {
             match peek {
                 Operator::And | Operator::Or => {
                     self.lexer.next_token();
-                    comp = ASTNode::Binary(peek, Box::new(comp), Box::new(self.parse_comparison()?));
+                    comp = ASTNode::binary(peek, comp, self.parse_comparison()?);
                 }
                 _ => break,
             }
@@ -30,7 +139,7 @@ impl Parser {
         Ok(comp)
     }
 
-    fn parse_comparison(&mut self) -> Result<ASTNode, String> {
+    fn parse_comparison(&mut self) -> Result<ASTNode, CalcError> {
         let expr: ASTNode = self.parse_expression()?;
         let mut operators: Vec<Operator> = vec![];
         let mut operands: Vec<ASTNode> = vec![];
@@ -44,20 +153,21 @@ impl Parser {
                 _ => break
             }
         }
-        if !operators.is_empty() && !operands.is_empty() {
+        if let Some(last) = operands.last() {
+            let span: Span = expr.span.to(last.span);
             operands.insert(0, expr);
-            return Ok(ASTNode::Comparison(operators, operands));
+            return Ok(ASTNode::new(ASTNodeKind::Comparison(operators, operands), span));
         }
         Ok(expr)
     }
 
-    fn parse_expression(&mut self) -> Result<ASTNode, String> {
+    fn parse_expression(&mut self) -> Result<ASTNode, CalcError> {
         let mut bw: ASTNode = self.parse_bitwise()?;
         while let Token::Operator(peek) = self.lexer.peek() {
             match peek {
                 Operator::Add | Operator::Subtract => {
                     self.lexer.next_token();
-                    bw = ASTNode::Binary(peek, Box::new(bw), Box::new(self.parse_bitwise()?));
+                    bw = ASTNode::binary(peek, bw, self.parse_bitwise()?);
                 }
                 _ => break,
             }
@@ -65,13 +175,13 @@ impl Parser {
         Ok(bw)
     }
 
-    fn parse_bitwise(&mut self) -> Result<ASTNode, String> {
+    fn parse_bitwise(&mut self) -> Result<ASTNode, CalcError> {
         let mut bwshift: ASTNode = self.parse_bwshifts()?;
         while let Token::Operator(peek) = self.lexer.peek() {
             match peek {
                 Operator::BWOr | Operator::BWAnd | Operator::BWXor => {
                     self.lexer.next_token();
-                    bwshift = ASTNode::Binary(peek, Box::new(bwshift), Box::new(self.parse_bwshifts()?));
+                    bwshift = ASTNode::binary(peek, bwshift, self.parse_bwshifts()?);
                 }
                 _ => break,
             }
@@ -79,13 +189,13 @@ impl Parser {
         Ok(bwshift)
     }
 
-    fn parse_bwshifts(&mut self) -> Result<ASTNode, String> {
+    fn parse_bwshifts(&mut self) -> Result<ASTNode, CalcError> {
         let mut term: ASTNode = self.parse_term()?;
         while let Token::Operator(peek) = self.lexer.peek() {
             match peek {
                 Operator::BWLeftShift | Operator::BWRightShift => {
                     self.lexer.next_token();
-                    term = ASTNode::Binary(peek, Box::new(term), Box::new(self.parse_term()?));
+                    term = ASTNode::binary(peek, term, self.parse_term()?);
                 }
                 _ => break,
             }
@@ -93,13 +203,13 @@ impl Parser {
         Ok(term)
     }
 
-    fn parse_term(&mut self) -> Result<ASTNode, String> {
-        let mut factor: ASTNode = self.parse_factor()?;
+    fn parse_term(&mut self) -> Result<ASTNode, CalcError> {
+        let mut factor: ASTNode = self.parse_juxtaposition()?;
         while let Token::Operator(peek) = self.lexer.peek() {
             match peek {
                 Operator::Multiply | Operator::Divide | Operator::Modulo => {
                     self.lexer.next_token();
-                    factor = ASTNode::Binary(peek, Box::new(factor), Box::new(self.parse_factor()?));
+                    factor = ASTNode::binary(peek, factor, self.parse_juxtaposition()?);
                 }
                 _ => break,
             }
@@ -107,22 +217,70 @@ impl Parser {
         Ok(factor)
     }
 
-    fn parse_factor(&mut self) -> Result<ASTNode, String> {
+    // A name right after a factor multiplies it, binding tighter than `*` and `/` so `3 m / 2 s` is 1.5 m/s
+    fn parse_juxtaposition(&mut self) -> Result<ASTNode, CalcError> {
+        let mut factor: ASTNode = self.parse_factor()?;
+        while let Token::Identifier(name) = self.lexer.peek() {
+            if is_conversion_keyword(&name) {
+                break;
+            }
+            if !self.unit_follows(0) {
+                factor = ASTNode::binary(Operator::Multiply, factor, self.parse_factor()?);
+                continue;
+            }
+            factor = ASTNode::binary(Operator::Multiply, factor, self.parse_unit_factor()?);
+            // The rest of a compound unit like `m/s^2`, but not the `2 s` of `3 m / 2 s`
+            while let Token::Operator(operator @ (Operator::Multiply | Operator::Divide)) = self.lexer.peek() {
+                if !self.unit_follows(1) {
+                    break;
+                }
+                self.lexer.next_token();
+                factor = ASTNode::binary(operator, factor, self.parse_unit_factor()?);
+            }
+        }
+        Ok(factor)
+    }
+
+    // Whether the token `n` places ahead is a unit name rather than a call like `min(...)`
+    fn unit_follows(&self, n: usize) -> bool {
+        match self.lexer.peek_nth(n) {
+            Token::Identifier(name) => units::is_unit(&name) && !matches!(self.lexer.peek_nth(n + 1), Token::LeftParen),
+            _ => false,
+        }
+    }
+
+    // `km` or `s^2`
+    fn parse_unit_factor(&mut self) -> Result<ASTNode, CalcError> {
+        let (token, span) = self.lexer.next_token();
+        let mut item: ASTNode = match token {
+            Token::Identifier(name) => ASTNode::new(ASTNodeKind::Unit(name), span),
+            _ => return Err(CalcError::parse(format!("expected a unit, found {}", token), span)),
+        };
+        if let Token::Operator(Operator::Exponent) = self.lexer.peek() {
+            self.lexer.next_token();
+            item = ASTNode::binary(Operator::Exponent, item, self.parse_factor()?);
+        }
+        Ok(item)
+    }
+
+    fn parse_factor(&mut self) -> Result<ASTNode, CalcError> {
         let mut item: ASTNode = if let Token::Operator(op) = self.lexer.peek() {
             match op {
                 Operator::Add | Operator::Subtract | Operator::Not | Operator::BWNot => {
-                    self.lexer.next_token();
-                    ASTNode::Unary(op, Box::new(self.parse_factor()?))
+                    let (_, op_span) = self.lexer.next_token();
+                    let operand: ASTNode = self.parse_factor()?;
+                    let span: Span = op_span.to(operand.span);
+                    ASTNode::new(ASTNodeKind::Unary(op, Box::new(operand)), span)
                 }
-                _ => return Err(String::from("Parse error"))
+                _ => return Err(CalcError::parse(format!("unexpected `{}`", op), self.lexer.peek_span()))
             }
         } else {
-            self.parse_item()?
+            self.parse_index()?
         };
         while let Token::Operator(peek) = self.lexer.peek() {
             if let Operator::Exponent = peek {
                 self.lexer.next_token();
-                item = ASTNode::Binary(peek, Box::new(item), Box::new(self.parse_factor()?));
+                item = ASTNode::binary(peek, item, self.parse_factor()?);
             } else {
                 break;
             }
@@ -130,47 +288,235 @@ impl Parser {
         Ok(item)
     }
 
-    fn parse_item(&mut self) -> Result<ASTNode, String> {
-        let token: Token = self.lexer.next_token();
+    // `v[0]`, `m[1][0]`
+    fn parse_index(&mut self) -> Result<ASTNode, CalcError> {
+        let mut item: ASTNode = self.parse_item()?;
+        while let Token::LeftBracket = self.lexer.peek() {
+            self.lexer.next_token(); // Consume LeftBracket
+            let index: ASTNode = self.parse_from_top()?;
+            let close_span: Span = self.expect_right_bracket("expected `]` after index")?;
+            let span: Span = item.span.to(close_span);
+            item = ASTNode::new(ASTNodeKind::Index(Box::new(item), Box::new(index)), span);
+        }
+        Ok(item)
+    }
+
+    fn parse_item(&mut self) -> Result<ASTNode, CalcError> {
+        let (token, span) = self.lexer.next_token();
         match token {
-            Token::Number(x) => Ok(ASTNode::Number(x)),
-            Token::Bool(x) => Ok(ASTNode::Bool(x)),
+            Token::Number(x) => Ok(ASTNode::new(ASTNodeKind::Number(x), span)),
+            Token::Bool(x) => Ok(ASTNode::new(ASTNodeKind::Bool(x), span)),
             Token::Identifier(x) => {
                 if let Token::LeftParen = self.lexer.peek() {
                     let mut args: Vec<ASTNode> = Vec::new();
                     self.lexer.next_token(); // Consume LeftParen
+                    if !matches!(self.lexer.peek(), Token::RightParen) {
+                        loop {
+                            args.push(self.parse_from_top()?);
+                            if let Token::Comma = self.lexer.peek() {
+                                self.lexer.next_token(); // Consume Comma
+                            } else {
+                                break;
+                            }
+                        }
+                    }
+                    let close_span: Span = self.expect_right_paren("expected `)` after argument list")?;
+                    Ok(ASTNode::new(ASTNodeKind::Function(x, args), span.to(close_span)))
+                } else {
+                    Ok(ASTNode::new(ASTNodeKind::Variable(x), span))
+                }
+            }
+            Token::LeftParen => {
+                let mut expr: ASTNode = self.parse_from_top()?;
+                let close_span: Span = self.expect_right_paren("expected `)` after expression")?;
+                expr.span = span.to(close_span);
+                Ok(expr)
+            }
+            Token::LeftBracket => {
+                let mut items: Vec<ASTNode> = Vec::new();
+                if !matches!(self.lexer.peek(), Token::RightBracket) {
                     loop {
-                        args.push(self.parse_from_top()?);
+                        items.push(self.parse_from_top()?);
                         if let Token::Comma = self.lexer.peek() {
                             self.lexer.next_token(); // Consume Comma
                         } else {
                             break;
                         }
                     }
-                    self.lexer.next_token(); // Consume RPA
-                    Ok(ASTNode::Function(x, args))
-                } else {
-                    Ok(ASTNode::Variable(x))
                 }
+                let close_span: Span = self.expect_right_bracket("expected `]` after list")?;
+                Ok(ASTNode::new(ASTNodeKind::List(items), span.to(close_span)))
             }
-            Token::LeftParen => {
-                let expr: ASTNode = self.parse_from_top()?;
-                self.lexer.next_token(); // Consume RPA
-                Ok(expr)
+            Token::Eof => Err(CalcError::parse("unexpected end of input", span)),
+            _ => Err(CalcError::parse(format!("unexpected {}", token), span)),
+        }
+    }
+
+    fn expect_right_paren(&mut self, message: &str) -> Result<Span, CalcError> {
+        if let Token::RightParen = self.lexer.peek() {
+            let (_, span) = self.lexer.next_token(); // Consume RPA
+            Ok(span)
+        } else {
+            Err(CalcError::parse(format!("{}, found {}", message, self.lexer.peek()), self.lexer.peek_span()))
+        }
+    }
+
+    fn expect_right_bracket(&mut self, message: &str) -> Result<Span, CalcError> {
+        if let Token::RightBracket = self.lexer.peek() {
+            let (_, span) = self.lexer.next_token();
+            Ok(span)
+        } else {
+            Err(CalcError::parse(format!("{}, found {}", message, self.lexer.peek()), self.lexer.peek_span()))
+        }
+    }
+
+    fn parse_from_top(&mut self) -> Result<ASTNode, CalcError> {
+        self.parse_and_or()
+    }
+
+    // Assignments are only allowed at the top level, e.g. `x = 3*4` or `f(x, y) = x^2 + y`
+    fn parse_assignment(&mut self) -> Result<ASTNode, CalcError> {
+        let target: ASTNode = self.parse_from_top()?;
+        if let Token::Assign = self.lexer.peek() {
+            let (_, assign_span) = self.lexer.next_token();
+            return match target.kind {
+                ASTNodeKind::Variable(name) => {
+                    let value: ASTNode = self.parse_from_top()?;
+                    let span: Span = target.span.to(value.span);
+                    Ok(ASTNode::new(ASTNodeKind::Assign(name, Box::new(value)), span))
+                }
+                ASTNodeKind::Function(name, args) => {
+                    let mut params: Vec<String> = Vec::new();
+                    for arg in args {
+                        match arg.kind {
+                            ASTNodeKind::Variable(param) if !params.contains(&param) => params.push(param),
+                            ASTNodeKind::Variable(param) => return Err(CalcError::parse(format!("duplicate parameter `{}`", param), arg.span)),
+                            _ => return Err(CalcError::parse("expected a parameter name", arg.span)),
+                        }
+                    }
+                    let body: ASTNode = self.parse_from_top()?;
+                    let span: Span = target.span.to(body.span);
+                    Ok(ASTNode::new(ASTNodeKind::FunctionDef(name, params, Box::new(body)), span))
+                }
+                _ => Err(CalcError::parse("invalid assignment target, expected a variable or function signature", target.span.to(assign_span))),
+            };
+        }
+        Ok(target)
+    }
+
+    // Trailing `to hex`, `to fixed 4`, `in km/h` etc., only allowed at the top level
+    fn parse_conversion_suffix(&mut self, node: ASTNode, keyword: &str) -> Result<ASTNode, CalcError> {
+        self.lexer.next_token(); // Consume the keyword
+        let (token, name_span) = self.lexer.next_token();
+        let name: String = match token {
+            Token::Identifier(name) => name,
+            _ => return Err(CalcError::parse(format!("expected a format or unit after `{}`, found {}", keyword, token), name_span)),
+        };
+        if !Format::is_name(&name) {
+            let (unit, unit_span) = self.parse_unit(name, name_span)?;
+            let span: Span = node.span.to(unit_span);
+            return Ok(ASTNode::new(ASTNodeKind::Convert(Box::new(node), unit), span));
+        }
+        let mut suffix_span: Span = name_span;
+        let mut digits: Option<usize> = None;
+        if let Token::Number(count) = self.lexer.peek() {
+            let (_, count_span) = self.lexer.next_token();
+            suffix_span = suffix_span.to(count_span);
+            match count.parse::<usize>() {
+                Ok(count) if count <= Format::MAX_DIGITS => digits = Some(count),
+                Ok(_) => return Err(CalcError::parse(format!("expected at most {} digits", Format::MAX_DIGITS), count_span)),
+                Err(_) => return Err(CalcError::parse("expected a whole number of digits", count_span)),
             }
-            Token::EOF => {
-                println!("Encountered Token::EOF");
-                Err(String::from("Parse error"))
+        }
+        match Format::from_name(&name, digits) {
+            Some(format) => {
+                let span: Span = node.span.to(suffix_span);
+                Ok(ASTNode::new(ASTNodeKind::Format(Box::new(node), format), span))
             }
-            _ => Err(String::from("Parse error")),
+            None => Err(CalcError::parse(format!("unknown format `{}`, expected one of: {}", name, Format::NAMES.join(", ")), suffix_span)),
         }
     }
 
-    pub fn parse_from_top(&mut self) -> Result<ASTNode, String> {
-        self.parse_and_or()
+    // Conversion target like `km`, `m/s^2` or `kg*m^-3`
+    fn parse_unit(&mut self, first: String, first_span: Span) -> Result<(Unit, Span), CalcError> {
+        let mut unit: Unit = Unit::default();
+        let (mut name, mut name_span) = (first, first_span);
+        let mut span: Span = first_span;
+        let mut sign: i32 = 1;
+        loop {
+            if !units::is_unit(&name) {
+                return Err(CalcError::parse(format!("unknown unit or format `{}`", name), name_span));
+            }
+            let mut power: i32 = 1;
+            if let Token::Operator(Operator::Exponent) = self.lexer.peek() {
+                self.lexer.next_token();
+                let negative: bool = matches!(self.lexer.peek(), Token::Operator(Operator::Subtract));
+                if negative {
+                    self.lexer.next_token();
+                }
+                let (token, power_span) = self.lexer.next_token();
+                span = span.to(power_span);
+                power = match token {
+                    Token::Number(digits) => match digits.parse::<i32>() {
+                        Ok(digits) => if negative { -digits } else { digits },
+                        Err(_) => return Err(CalcError::parse("expected an integer power", power_span)),
+                    },
+                    _ => return Err(CalcError::parse(format!("expected an integer power, found {}", token), power_span)),
+                };
+            }
+            unit.push(&name, sign * power);
+            sign = match self.lexer.peek() {
+                Token::Operator(Operator::Multiply) => 1,
+                Token::Operator(Operator::Divide) => -1,
+                _ => return Ok((unit, span)),
+            };
+            self.lexer.next_token();
+            let (token, token_span) = self.lexer.next_token();
+            name = match token {
+                Token::Identifier(name) => name,
+                _ => return Err(CalcError::parse(format!("expected a unit, found {}", token), token_span)),
+            };
+            name_span = token_span;
+            span = span.to(token_span);
+        }
+    }
+
+    pub fn parse(&mut self) -> Result<ASTNode, CalcError> {
+        let mut node: ASTNode = self.parse_assignment()?;
+        if let Token::Identifier(word) = self.lexer.peek() {
+            if is_conversion_keyword(&word) {
+                node = self.parse_conversion_suffix(node, &word)?;
+            }
+        }
+        match self.lexer.peek() {
+            Token::Eof => Ok(node),
+            token => Err(CalcError::parse(format!("unexpected {}", token), self.lexer.peek_span())),
+        }
     }
 
     pub fn new(lexer: Lexer) -> Parser {
         Parser { lexer }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> ASTNode {
+        Parser::new(Lexer::new(input).unwrap()).parse().unwrap()
+    }
+
+    #[test]
+    fn nodes_carry_spans() {
+        let node: ASTNode = parse("max(1, 2) * (3 + 4)");
+        assert_eq!(node.span, Span::new(0, 19));
+        match node.kind {
+            ASTNodeKind::Binary(Operator::Multiply, left, right) => {
+                assert_eq!(left.span, Span::new(0, 9));
+                assert_eq!(right.span, Span::new(12, 19));
+            }
+            kind => panic!("expected a product, got {:?}", kind),
+        }
+    }
+}
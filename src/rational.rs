@@ -0,0 +1,137 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Pow, Signed, ToPrimitive, Zero};
+
+use crate::{builtins::{round_digits, Builtin}, error::CalcError, functions::CallContext, interpreter::Value, lexer::*, numeric};
+
+// Exact results past this size take ages to compute and print, and are almost always a typo
+pub const MAX_BITS: u64 = 1 << 20;
+
+/// Whole numbers become [`Value::Integer`], everything else stays a fraction.
+pub fn normalize(value: BigRational) -> Value {
+    if value.is_integer() {
+        Value::Integer(value.to_integer())
+    } else {
+        Value::Rational(value)
+    }
+}
+
+pub fn to_f64(value: &BigRational) -> f64 {
+    value.to_f64().unwrap_or(f64::NAN)
+}
+
+/// Exact value of `x`, `None` for infinity and NaN.
+pub fn from_f64(value: f64) -> Option<BigRational> {
+    BigRational::from_float(value)
+}
+
+pub fn operation(operator: &Operator, a: BigRational, b: BigRational, span: Span) -> Result<Value, CalcError> {
+    let result: BigRational = match operator {
+        Operator::Add => a + b,
+        Operator::Subtract => a - b,
+        Operator::Multiply => a * b,
+        Operator::Divide | Operator::Modulo if b.is_zero() => return Err(CalcError::domain("division by zero", span)),
+        Operator::Divide => a / b,
+        Operator::Modulo => a % b,
+        Operator::Exponent => return power(&a, &b, span),
+        _ => return Err(CalcError::parse("Invalid binary operator", span)),
+    };
+    Ok(normalize(result))
+}
+
+// Exact for integer exponents, only fractional ones leave the rationals
+fn power(base: &BigRational, exponent: &BigRational, span: Span) -> Result<Value, CalcError> {
+    if !exponent.is_integer() {
+        return Ok(Value::Number(numeric::float_power(to_f64(base), to_f64(exponent), span)?));
+    }
+    let exponent: BigInt = exponent.to_integer();
+    if exponent.is_negative() && base.is_zero() {
+        return Err(CalcError::domain("division by zero", span));
+    }
+    // 0, 1 and -1 stay small whatever the exponent
+    if base.is_zero() || base.abs().is_one() {
+        let negative: bool = base.is_negative() && exponent.bit(0);
+        return Ok(normalize(if base.is_zero() && !exponent.is_zero() { base.clone() } else if negative { -BigRational::one() } else { BigRational::one() }));
+    }
+    let bits: u64 = base.numer().bits().max(base.denom().bits());
+    match exponent.to_i32() {
+        Some(exponent) if bits.saturating_mul(exponent.unsigned_abs() as u64) <= MAX_BITS => Ok(normalize(base.pow(exponent))),
+        _ => Err(CalcError::overflow(format!("exact result of `^` would exceed {} bits", MAX_BITS), span)),
+    }
+}
+
+// Builtins that keep rationals exact, `None` for the ones computed through f64
+fn exact_builtin(name: &str, args: &[BigRational], span: Span) -> Option<Result<Value, CalcError>> {
+    let x: &BigRational = args.first()?;
+    let result: BigRational = match name {
+        "abs" => x.abs(),
+        "floor" => x.floor(),
+        "ceil" => x.ceil(),
+        "trunc" => x.trunc(),
+        "fract" => x.fract(),
+        "sign" => x.signum(),
+        "round" => {
+            let digits: i32 = match args.get(1) {
+                Some(digits) => match round_digits(to_f64(digits), span) {
+                    Ok(digits) => digits,
+                    Err(error) => return Some(Err(error)),
+                },
+                None => 0,
+            };
+            let scale: BigRational = BigRational::from_integer(BigInt::from(10)).pow(digits);
+            (x * &scale).round() / scale
+        }
+        "pow" => return Some(power(x, &args[1], span)),
+        _ => return None,
+    };
+    Some(Ok(normalize(result)))
+}
+
+/// Calls `builtin` with exact arguments only, keeping the result exact
+/// where the function allows it and promoting to a float otherwise.
+pub fn call_builtin(builtin: &Builtin, args: &[BigRational], context: &CallContext) -> Result<Value, CalcError> {
+    if let Some(result) = exact_builtin(builtin.name, args, context.span) {
+        return result;
+    }
+    let floats: Vec<f64> = args.iter().map(to_f64).collect();
+    Ok(Value::Number(builtin.call_float(&floats, context.span)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::ErrorKind, interpreter::Value, testing::*};
+
+    #[test]
+    fn exact_arithmetic() {
+        assert_eq!(eval("1/3 + 1/6"), "1/2");
+        assert_eq!(eval("2/3 * 3/4"), "1/2");
+        assert_eq!(eval("(2/3)^2"), "4/9");
+        assert_eq!(eval("(1/2)^-2"), "4");
+        assert_eq!(eval("1/3 - 1/3"), "0");
+        assert_eq!(error_kind("1/0"), ErrorKind::Domain);
+    }
+
+    #[test]
+    fn shows_decimal_expansion() {
+        assert_eq!(render("1/3"), Ok(String::from("1/3 (0.3333333333333333)")));
+        assert_eq!(render("1/4 + 1/4"), Ok(String::from("1/2 (0.5)")));
+    }
+
+    #[test]
+    fn irrational_builtins_give_floats() {
+        assert!(matches!(crate::eval("sqrt(1/4)"), Ok(Value::Number(value)) if value == 0.5));
+        assert!(matches!(crate::eval("sin(1/2)"), Ok(Value::Number(_))));
+        assert!(matches!(crate::eval("1/2 + 0.5"), Ok(Value::Number(value)) if value == 1f64));
+        assert_eq!(eval("floor(7/2)"), "3");
+    }
+
+    #[test]
+    fn rounds_exactly() {
+        assert_eq!(eval("round(1/3, 5)"), "33333/100000");
+        assert_eq!(eval("round(7/2)"), "4");
+        assert_eq!(eval("round(-7/2)"), "-4");
+        assert_eq!(error_kind("round(1/3, 100000000)"), ErrorKind::Domain);
+        assert_eq!(error_kind("round(1/3, 10^12)"), ErrorKind::Domain);
+        assert_eq!(error_kind("round(1/3, 1/2)"), ErrorKind::Domain);
+    }
+}
@@ -0,0 +1,201 @@
+use std::cmp::Ordering;
+
+use num_bigint::BigInt;
+
+use crate::{builtins::{Builtin, BUILTINS}, error::CalcError, functions::{CallContext, Function}, interpreter::Value, lexer::*, numeric};
+
+// Builtins that reduce any number of values, given as arguments or inside lists
+const AGGREGATES: &[&str] = &["count", "max", "mean", "median", "min", "mode", "percentile", "product", "stdev", "stdevp", "sum", "var", "varp"];
+
+pub fn is_aggregate(name: &str) -> bool {
+    AGGREGATES.contains(&name)
+}
+
+fn integer(value: usize) -> Value {
+    Value::Integer(BigInt::from(value))
+}
+
+// `mean(1, [2, 3], [[4]])` averages 1, 2, 3 and 4
+fn flatten(args: &[Value], values: &mut Vec<Value>) {
+    for arg in args {
+        match arg {
+            Value::List(items) => flatten(items, values),
+            value => values.push(value.clone()),
+        }
+    }
+}
+
+struct Statistics<'a> {
+    name: &'a str,
+    context: &'a CallContext<'a>,
+}
+
+impl Statistics<'_> {
+    fn apply(&self, operator: Operator, a: &Value, b: &Value) -> Result<Value, CalcError> {
+        numeric::binary_operation(&operator, a.clone(), b.clone(), self.context.span, self.context.settings)
+    }
+
+    fn non_empty(&self, values: &[Value], needed: usize) -> Result<(), CalcError> {
+        if values.len() < needed {
+            let plural: &str = if needed == 1 { "" } else { "s" };
+            return Err(CalcError::domain(format!("{} needs at least {} value{}, got {}", self.name, needed, plural, values.len()), self.context.span));
+        }
+        Ok(())
+    }
+
+    fn compare(&self, a: &Value, b: &Value) -> Result<Ordering, CalcError> {
+        numeric::compare(a, b).ok_or_else(|| CalcError::type_error(format!("{} cannot order {} and {}", self.name, a, b), self.context.span))
+    }
+
+    fn sorted(&self, mut values: Vec<Value>) -> Result<Vec<Value>, CalcError> {
+        let mut error: Option<CalcError> = None;
+        values.sort_by(|a, b| self.compare(a, b).unwrap_or_else(|e| {
+            error.get_or_insert(e);
+            Ordering::Equal
+        }));
+        match error {
+            Some(error) => Err(error),
+            None => Ok(values),
+        }
+    }
+
+    // Starting from the first value rather than 0 or 1 lets quantities keep their unit
+    fn fold(&self, values: &[Value], operator: Operator, empty: usize) -> Result<Value, CalcError> {
+        match values.split_first() {
+            Some((first, rest)) => rest.iter().try_fold(first.clone(), |total, value| self.apply(operator.clone(), &total, value)),
+            None => Ok(integer(empty)),
+        }
+    }
+
+    fn sum(&self, values: &[Value]) -> Result<Value, CalcError> {
+        self.fold(values, Operator::Add, 0)
+    }
+
+    fn mean(&self, values: &[Value]) -> Result<Value, CalcError> {
+        self.non_empty(values, 1)?;
+        self.apply(Operator::Divide, &self.sum(values)?, &integer(values.len()))
+    }
+
+    // Linear interpolation between the closest ranks, `p` from 0 to 100
+    fn percentile(&self, values: Vec<Value>, p: &Value) -> Result<Value, CalcError> {
+        self.non_empty(&values, 1)?;
+        let in_range: bool = matches!(numeric::to_float(p), Some(p) if (0f64..=100f64).contains(&p));
+        if !in_range {
+            return Err(CalcError::domain(format!("percentile must be between 0 and 100, got {}", p), self.context.span));
+        }
+        let values: Vec<Value> = self.sorted(values)?;
+        let rank: Value = self.apply(Operator::Divide, &self.apply(Operator::Multiply, p, &integer(values.len() - 1))?, &integer(100))?;
+        let lower: usize = numeric::to_float(&rank).unwrap().floor() as usize;
+        let upper: usize = (lower + 1).min(values.len() - 1);
+        let fraction: Value = self.apply(Operator::Subtract, &rank, &integer(lower))?;
+        let step: Value = self.apply(Operator::Subtract, &values[upper], &values[lower])?;
+        self.apply(Operator::Add, &values[lower], &self.apply(Operator::Multiply, &step, &fraction)?)
+    }
+
+    // Sample variance divides by n - 1, population variance by n
+    fn variance(&self, values: &[Value], sample: bool) -> Result<Value, CalcError> {
+        self.non_empty(values, if sample { 2 } else { 1 })?;
+        let mean: Value = self.mean(values)?;
+        let squares: Vec<Value> = values.iter().map(|value| {
+            let deviation: Value = self.apply(Operator::Subtract, value, &mean)?;
+            self.apply(Operator::Multiply, &deviation, &deviation)
+        }).collect::<Result<Vec<Value>, CalcError>>()?;
+        self.apply(Operator::Divide, &self.sum(&squares)?, &integer(if sample { values.len() - 1 } else { values.len() }))
+    }
+
+    // Through the sqrt builtin so quantities keep their unit and decimals their precision
+    fn stdev(&self, values: &[Value], sample: bool) -> Result<Value, CalcError> {
+        let sqrt: &Builtin = BUILTINS.iter().find(|builtin| builtin.name == "sqrt").unwrap();
+        sqrt.call(&[self.variance(values, sample)?], self.context)
+    }
+
+    // The most common value, the smallest of them on a tie
+    fn mode(&self, values: Vec<Value>) -> Result<Value, CalcError> {
+        self.non_empty(&values, 1)?;
+        let values: Vec<Value> = self.sorted(values)?;
+        let (mut best, mut best_count, mut run) = (0usize, 0usize, 0usize);
+        for i in 0..values.len() {
+            run = if i > 0 && self.compare(&values[i - 1], &values[i])? == Ordering::Equal { run + 1 } else { 1 };
+            if run > best_count {
+                best = i;
+                best_count = run;
+            }
+        }
+        Ok(values[best].clone())
+    }
+
+    fn extreme(&self, values: &[Value], wanted: Ordering) -> Result<Value, CalcError> {
+        self.non_empty(values, 1)?;
+        let mut best: &Value = &values[0];
+        for value in values[1..].iter() {
+            if self.compare(value, best)? == wanted {
+                best = value;
+            }
+        }
+        Ok(best.clone())
+    }
+}
+
+/// Calls one of the aggregate builtins, which keep values exact and accept
+/// any mix of numbers and lists.
+pub fn call_builtin(builtin: &Builtin, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+    let statistics: Statistics = Statistics { name: builtin.name, context };
+    let mut values: Vec<Value> = Vec::new();
+    if builtin.name == "percentile" {
+        let (p, data) = args.split_last().unwrap();
+        flatten(data, &mut values);
+        return statistics.percentile(values, p);
+    }
+    flatten(args, &mut values);
+    match builtin.name {
+        "count" => Ok(integer(values.len())),
+        "sum" => statistics.sum(&values),
+        "product" => statistics.fold(&values, Operator::Multiply, 1),
+        "mean" => statistics.mean(&values),
+        "median" => statistics.percentile(values, &integer(50)),
+        "mode" => statistics.mode(values),
+        "var" => statistics.variance(&values, true),
+        "varp" => statistics.variance(&values, false),
+        "stdev" => statistics.stdev(&values, true),
+        "stdevp" => statistics.stdev(&values, false),
+        "min" => statistics.extreme(&values, Ordering::Less),
+        "max" => statistics.extreme(&values, Ordering::Greater),
+        name => Err(CalcError::unknown_identifier(format!("unknown function `{}`", name), context.span)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::ErrorKind, testing::*};
+
+    #[test]
+    fn averages() {
+        assert_eq!(eval("mean(1, 2, 3, 4)"), eval("mean([1, 2, 3, 4])"));
+        assert_eq!(eval("mean([1, 2], 3, [4])"), "5/2");
+        assert_eq!(eval("median(3, 1, 2)"), "2");
+        assert_eq!(eval("median([4, 1, 3, 2])"), "5/2");
+        assert_eq!(eval("mode(1, 2, 2, 3)"), "2");
+        assert_eq!(eval("percentile([1, 2, 3, 4, 5], 50)"), "3");
+        assert_eq!(error_kind("percentile([1, 2], 150)"), ErrorKind::Domain);
+    }
+
+    #[test]
+    fn spread() {
+        assert_eq!(eval("varp(2, 4, 4, 4, 5, 5, 7, 9)"), "4");
+        assert_eq!(eval("stdevp([2, 4, 4, 4, 5, 5, 7, 9])"), "2");
+        assert_eq!(eval("var(2, 4, 4, 4, 5, 5, 7, 9)"), "32/7");
+        assert_eq!(eval_float("stdev(2, 4, 4, 4, 5, 5, 7, 9)"), (32f64 / 7f64).sqrt());
+        assert_eq!(error_kind("var(5)"), ErrorKind::Domain);
+    }
+
+    #[test]
+    fn totals_and_extremes() {
+        assert_eq!(eval("sum([1, 2], [3])"), "6");
+        assert_eq!(eval("product(1, 2, 3, 4)"), "24");
+        assert_eq!(eval("count([1, 2, 3], 4)"), "4");
+        assert_eq!(eval("min(3, 1, 2)"), "1");
+        assert_eq!(eval("max([3, 1, 2])"), "3");
+        assert_eq!(error_kind("mean()"), ErrorKind::Arity);
+        assert_eq!(error_kind("mean(1, true)"), ErrorKind::Type);
+    }
+}
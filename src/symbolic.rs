@@ -0,0 +1,564 @@
+use num_bigint::BigInt;
+use num_traits::{One, Signed, Zero};
+
+use crate::{angle::AngleMode, environment::*, error::CalcError, interpreter::Value, lexer::*, numeric, parser::*};
+
+pub const SYMBOLIC_FORMS: &[&str] = &["diff", "simplify"];
+
+pub fn is_symbolic_form(name: &str) -> bool {
+    SYMBOLIC_FORMS.contains(&name)
+}
+
+fn node(kind: ASTNodeKind) -> ASTNode {
+    ASTNode::new(kind, Span::default())
+}
+
+fn number(value: i64) -> ASTNode {
+    constant_node(&Value::Integer(BigInt::from(value)))
+}
+
+fn variable(name: &str) -> ASTNode {
+    node(ASTNodeKind::Variable(name.to_string()))
+}
+
+fn call(name: &str, arg: ASTNode) -> ASTNode {
+    node(ASTNodeKind::Function(name.to_string(), vec![arg]))
+}
+
+fn binary(operator: Operator, left: ASTNode, right: ASTNode) -> ASTNode {
+    node(ASTNodeKind::Binary(operator, Box::new(left), Box::new(right)))
+}
+
+fn negate(operand: ASTNode) -> ASTNode {
+    node(ASTNodeKind::Unary(Operator::Subtract, Box::new(operand)))
+}
+
+fn add(left: ASTNode, right: ASTNode) -> ASTNode {
+    binary(Operator::Add, left, right)
+}
+
+fn subtract(left: ASTNode, right: ASTNode) -> ASTNode {
+    binary(Operator::Subtract, left, right)
+}
+
+fn multiply(left: ASTNode, right: ASTNode) -> ASTNode {
+    binary(Operator::Multiply, left, right)
+}
+
+fn divide(left: ASTNode, right: ASTNode) -> ASTNode {
+    binary(Operator::Divide, left, right)
+}
+
+fn power(base: ASTNode, exponent: ASTNode) -> ASTNode {
+    binary(Operator::Exponent, base, exponent)
+}
+
+fn depends_on(node: &ASTNode, name: &str) -> bool {
+    match &node.kind {
+        ASTNodeKind::Variable(variable) | ASTNodeKind::Unit(variable) => variable == name,
+        ASTNodeKind::Number(_) | ASTNodeKind::Bool(_) => false,
+        ASTNodeKind::Unary(_, operand) => depends_on(operand, name),
+        ASTNodeKind::Binary(_, left, right) | ASTNodeKind::Index(left, right) => depends_on(left, name) || depends_on(right, name),
+        ASTNodeKind::Function(_, args) | ASTNodeKind::List(args) | ASTNodeKind::Comparison(_, args) => args.iter().any(|arg| depends_on(arg, name)),
+        _ => true,
+    }
+}
+
+// Value of a subtree made only of number literals, kept exact where possible
+fn constant(node: &ASTNode) -> Option<Value> {
+    let span: Span = node.span;
+    let value: Value = match &node.kind {
+        ASTNodeKind::Number(literal) => numeric::parse_literal(literal, &Settings::default(), span).ok()?,
+        ASTNodeKind::Unary(Operator::Subtract, operand) => numeric::negate(constant(operand)?, span).ok()?,
+        ASTNodeKind::Binary(operator @ (Operator::Add | Operator::Subtract | Operator::Multiply | Operator::Divide | Operator::Exponent), left, right) => {
+            numeric::binary_operation(operator, constant(left)?, constant(right)?, span, &Settings::default()).ok()?
+        }
+        _ => return None,
+    };
+    match value {
+        Value::Number(number) if !number.is_finite() => None,
+        Value::Integer(_) | Value::Rational(_) | Value::Number(_) => Some(value),
+        _ => None,
+    }
+}
+
+fn constant_node(value: &Value) -> ASTNode {
+    let literal = |text: String| node(ASTNodeKind::Number(text));
+    match value {
+        Value::Integer(value) if value.is_negative() => negate(literal((-value).to_string())),
+        Value::Rational(value) if value.is_negative() => negate(constant_node(&Value::Rational(-value))),
+        Value::Rational(value) => divide(literal(value.numer().to_string()), literal(value.denom().to_string())),
+        Value::Number(value) if *value < 0f64 => negate(literal((-value).to_string())),
+        value => literal(value.to_string()),
+    }
+}
+
+fn is_value(node: &ASTNode, expected: i64) -> bool {
+    matches!(constant(node), Some(value) if numeric::compare(&value, &Value::Integer(BigInt::from(expected))) == Some(std::cmp::Ordering::Equal))
+}
+
+fn add_values(a: &Value, b: &Value) -> Value {
+    numeric::binary_operation(&Operator::Add, a.clone(), b.clone(), Span::default(), &Settings::default()).unwrap()
+}
+
+fn multiply_values(a: &Value, b: &Value) -> Value {
+    numeric::binary_operation(&Operator::Multiply, a.clone(), b.clone(), Span::default(), &Settings::default()).unwrap()
+}
+
+fn is_zero(value: &Value) -> bool {
+    match value {
+        Value::Integer(value) => value.is_zero(),
+        Value::Number(value) => *value == 0f64,
+        _ => false,
+    }
+}
+
+fn is_one(value: &Value) -> bool {
+    match value {
+        Value::Integer(value) => value.is_one(),
+        Value::Number(value) => *value == 1f64,
+        _ => false,
+    }
+}
+
+fn is_negative(value: &Value) -> bool {
+    numeric::compare(value, &Value::Integer(BigInt::zero())) == Some(std::cmp::Ordering::Less)
+}
+
+// A sum as coefficient * term pairs, like terms being those that print the same once their factors are sorted
+fn collect_terms(node: ASTNode, sign: Value, terms: &mut Vec<(Value, Option<ASTNode>)>) {
+    match node.kind {
+        ASTNodeKind::Binary(Operator::Add, left, right) => {
+            collect_terms(*left, sign.clone(), terms);
+            collect_terms(*right, sign, terms);
+        }
+        ASTNodeKind::Binary(Operator::Subtract, left, right) => {
+            collect_terms(*left, sign.clone(), terms);
+            collect_terms(*right, multiply_values(&sign, &Value::Integer(BigInt::from(-1))), terms);
+        }
+        ASTNodeKind::Unary(Operator::Subtract, operand) => collect_terms(*operand, multiply_values(&sign, &Value::Integer(BigInt::from(-1))), terms),
+        kind => {
+            let term: ASTNode = node_with(kind, node.span);
+            let (coefficient, rest) = split_coefficient(term);
+            let coefficient: Value = multiply_values(&sign, &coefficient);
+            let key: Option<String> = rest.as_ref().map(|rest| rest.to_string());
+            match terms.iter_mut().find(|(_, other)| other.as_ref().map(|other| other.to_string()) == key) {
+                Some((total, _)) => *total = add_values(total, &coefficient),
+                None => terms.push((coefficient, rest)),
+            }
+        }
+    }
+}
+
+fn node_with(kind: ASTNodeKind, span: Span) -> ASTNode {
+    ASTNode::new(kind, span)
+}
+
+// `3*x*y` is 3 times `x*y`, a lone constant has no term at all
+fn split_coefficient(term: ASTNode) -> (Value, Option<ASTNode>) {
+    if let Some(value) = constant(&term) {
+        return (value, None);
+    }
+    let mut coefficient: Value = Value::Integer(BigInt::one());
+    let mut factors: Vec<(ASTNode, ASTNode)> = Vec::new();
+    collect_factors(term, false, &mut coefficient, &mut factors);
+    (coefficient, Some(build_product(Value::Integer(BigInt::one()), factors)))
+}
+
+fn rebuild_sum(terms: Vec<(Value, Option<ASTNode>)>) -> ASTNode {
+    let mut constant_total: Value = Value::Integer(BigInt::zero());
+    let mut result: Option<ASTNode> = None;
+    for (coefficient, rest) in terms {
+        let rest: ASTNode = match rest {
+            Some(rest) => rest,
+            None => {
+                constant_total = add_values(&constant_total, &coefficient);
+                continue;
+            }
+        };
+        if is_zero(&coefficient) {
+            continue;
+        }
+        let negative: bool = is_negative(&coefficient);
+        let magnitude: Value = if negative { numeric::negate(coefficient, Span::default()).unwrap() } else { coefficient };
+        let term: ASTNode = if is_one(&magnitude) { rest } else { simplify_product(multiply(constant_node(&magnitude), rest)) };
+        result = Some(match (result, negative) {
+            (None, false) => term,
+            (None, true) => simplify_product(negate(term)),
+            (Some(sum), false) => add(sum, term),
+            (Some(sum), true) => subtract(sum, term),
+        });
+    }
+    match result {
+        None => constant_node(&constant_total),
+        Some(sum) if is_zero(&constant_total) => sum,
+        Some(sum) if is_negative(&constant_total) => subtract(sum, constant_node(&numeric::negate(constant_total, Span::default()).unwrap())),
+        Some(sum) => add(sum, constant_node(&constant_total)),
+    }
+}
+
+// A product as a coefficient and base^exponent factors, with equal bases merged
+fn collect_factors(node: ASTNode, inverted: bool, coefficient: &mut Value, factors: &mut Vec<(ASTNode, ASTNode)>) {
+    match node.kind {
+        ASTNodeKind::Binary(Operator::Multiply, left, right) => {
+            collect_factors(*left, inverted, coefficient, factors);
+            collect_factors(*right, inverted, coefficient, factors);
+        }
+        ASTNodeKind::Binary(Operator::Divide, left, right) => {
+            collect_factors(*left, inverted, coefficient, factors);
+            collect_factors(*right, !inverted, coefficient, factors);
+        }
+        ASTNodeKind::Unary(Operator::Subtract, operand) => {
+            *coefficient = multiply_values(coefficient, &Value::Integer(BigInt::from(-1)));
+            collect_factors(*operand, inverted, coefficient, factors);
+        }
+        kind => {
+            let factor: ASTNode = node_with(kind, node.span);
+            if let Some(value) = constant(&factor) {
+                let operator: Operator = if inverted { Operator::Divide } else { Operator::Multiply };
+                if let Ok(product) = numeric::binary_operation(&operator, coefficient.clone(), value, Span::default(), &Settings::default()) {
+                    *coefficient = product;
+                    return;
+                }
+            }
+            let (base, exponent) = match factor.kind {
+                ASTNodeKind::Binary(Operator::Exponent, base, exponent) => (*base, *exponent),
+                kind => (node_with(kind, factor.span), number(1)),
+            };
+            let exponent: ASTNode = if inverted { simplify_node(negate(exponent)) } else { exponent };
+            let key: String = base.to_string();
+            match factors.iter_mut().find(|(other, _)| other.to_string() == key) {
+                Some((_, total)) => *total = simplify_node(add(total.clone(), exponent)),
+                None => factors.push((base, exponent)),
+            }
+        }
+    }
+}
+
+fn simplify_product(node: ASTNode) -> ASTNode {
+    let mut coefficient: Value = Value::Integer(BigInt::one());
+    let mut factors: Vec<(ASTNode, ASTNode)> = Vec::new();
+    collect_factors(node, false, &mut coefficient, &mut factors);
+    build_product(coefficient, factors)
+}
+
+// Negative exponents and the denominator of a fractional coefficient go below the line
+fn build_product(coefficient: Value, mut factors: Vec<(ASTNode, ASTNode)>) -> ASTNode {
+    if is_zero(&coefficient) {
+        return number(0);
+    }
+    // A canonical order makes `x*y` and `y*x` the same term, plain variables go first
+    factors.sort_by_key(|(base, _)| (!matches!(base.kind, ASTNodeKind::Variable(_)), base.to_string()));
+    let (mut numerator, mut denominator): (Vec<ASTNode>, Vec<ASTNode>) = (Vec::new(), Vec::new());
+    let negative: bool = is_negative(&coefficient);
+    let magnitude: Value = if negative { numeric::negate(coefficient, Span::default()).unwrap() } else { coefficient };
+    match magnitude {
+        Value::Rational(value) => {
+            if !value.numer().is_one() {
+                numerator.push(constant_node(&Value::Integer(value.numer().clone())));
+            }
+            denominator.push(constant_node(&Value::Integer(value.denom().clone())));
+        }
+        value if !is_one(&value) => numerator.push(constant_node(&value)),
+        _ => (),
+    }
+    for (base, exponent) in factors {
+        if is_value(&exponent, 0) {
+            continue;
+        }
+        match constant(&exponent) {
+            Some(value) if is_negative(&value) => {
+                let positive: Value = numeric::negate(value, Span::default()).unwrap();
+                denominator.push(if is_one(&positive) { base } else { power(base, constant_node(&positive)) });
+            }
+            _ if is_value(&exponent, 1) => numerator.push(base),
+            _ => numerator.push(power(base, exponent)),
+        }
+    }
+    if numerator.is_empty() {
+        numerator.push(number(1));
+    }
+    if negative {
+        numerator[0] = negate(numerator[0].clone());
+    }
+    let numerator: ASTNode = numerator.into_iter().reduce(multiply).unwrap();
+    match denominator.into_iter().reduce(multiply) {
+        Some(denominator) => divide(numerator, denominator),
+        None => numerator,
+    }
+}
+
+fn simplify_node(node: ASTNode) -> ASTNode {
+    if let Some(value) = constant(&node) {
+        // Only fold what stays exact, or what was already a float
+        if matches!(value, Value::Integer(_) | Value::Rational(_)) || has_float_literal(&node) {
+            return constant_node(&value);
+        }
+    }
+    let span: Span = node.span;
+    match node.kind {
+        ASTNodeKind::Binary(operator @ (Operator::Add | Operator::Subtract), left, right) => {
+            let mut terms: Vec<(Value, Option<ASTNode>)> = Vec::new();
+            collect_terms(node_with(ASTNodeKind::Binary(operator, left, right), span), Value::Integer(BigInt::one()), &mut terms);
+            rebuild_sum(terms)
+        }
+        ASTNodeKind::Unary(Operator::Subtract, operand) => match operand.kind {
+            ASTNodeKind::Unary(Operator::Subtract, inner) => *inner,
+            kind => {
+                let mut terms: Vec<(Value, Option<ASTNode>)> = Vec::new();
+                collect_terms(node_with(kind, operand.span), Value::Integer(BigInt::from(-1)), &mut terms);
+                rebuild_sum(terms)
+            }
+        },
+        ASTNodeKind::Unary(Operator::Add, operand) => *operand,
+        ASTNodeKind::Binary(operator @ (Operator::Multiply | Operator::Divide), left, right) => {
+            simplify_product(node_with(ASTNodeKind::Binary(operator, left, right), span))
+        }
+        ASTNodeKind::Binary(Operator::Exponent, base, exponent) => {
+            if is_value(&exponent, 0) || is_value(&base, 1) {
+                return number(1);
+            }
+            if is_value(&exponent, 1) {
+                return *base;
+            }
+            // (x^a)^b is x^(a*b) only for whole b, `(x^2)^(1/2)` is |x| and not x
+            let whole: bool = matches!(constant(&exponent), Some(Value::Integer(_)));
+            match base.kind {
+                ASTNodeKind::Binary(Operator::Exponent, inner, inner_exponent) if constant(&inner_exponent).is_some() && whole => {
+                    simplify_node(power(*inner, simplify_node(multiply(*inner_exponent, *exponent))))
+                }
+                kind => power(node_with(kind, base.span), *exponent),
+            }
+        }
+        kind => node_with(kind, span),
+    }
+}
+
+fn has_float_literal(node: &ASTNode) -> bool {
+    match &node.kind {
+        ASTNodeKind::Number(literal) => !literal.chars().all(|c| c.is_ascii_digit()),
+        ASTNodeKind::Unary(_, operand) => has_float_literal(operand),
+        ASTNodeKind::Binary(_, left, right) => has_float_literal(left) || has_float_literal(right),
+        _ => false,
+    }
+}
+
+/// Simplifies `node` bottom up: folds constants, drops `+0`, `*1` and
+/// `^1`, and merges like terms and equal factors.
+pub fn simplify(node: ASTNode) -> ASTNode {
+    let span: Span = node.span;
+    let kind: ASTNodeKind = match node.kind {
+        ASTNodeKind::Unary(operator, operand) => ASTNodeKind::Unary(operator, Box::new(simplify(*operand))),
+        ASTNodeKind::Binary(operator, left, right) => ASTNodeKind::Binary(operator, Box::new(simplify(*left)), Box::new(simplify(*right))),
+        ASTNodeKind::Function(name, args) => ASTNodeKind::Function(name, args.into_iter().map(simplify).collect()),
+        ASTNodeKind::List(items) => ASTNodeKind::List(items.into_iter().map(simplify).collect()),
+        kind => kind,
+    };
+    simplify_node(ASTNode::new(kind, span))
+}
+
+// Replaces the parameters of a user function with the arguments of a call
+fn substitute(node: &ASTNode, bindings: &[(String, ASTNode)]) -> ASTNode {
+    let kind: ASTNodeKind = match &node.kind {
+        ASTNodeKind::Variable(name) | ASTNodeKind::Unit(name) => match bindings.iter().find(|(param, _)| param == name) {
+            Some((_, arg)) => return arg.clone(),
+            None => node.kind.clone(),
+        },
+        ASTNodeKind::Unary(operator, operand) => ASTNodeKind::Unary(operator.clone(), Box::new(substitute(operand, bindings))),
+        ASTNodeKind::Binary(operator, left, right) => ASTNodeKind::Binary(operator.clone(), Box::new(substitute(left, bindings)), Box::new(substitute(right, bindings))),
+        ASTNodeKind::Function(name, args) => ASTNodeKind::Function(name.clone(), args.iter().map(|arg| substitute(arg, bindings)).collect()),
+        kind => kind.clone(),
+    };
+    ASTNode::new(kind, node.span)
+}
+
+struct Differentiator<'a> {
+    variable: &'a str,
+    env: &'a Environment,
+    depth: usize,
+}
+
+impl Differentiator<'_> {
+    // Trig functions of degrees or gradians pick up a factor from converting to radians
+    fn angle_factor(&self) -> Option<ASTNode> {
+        match self.env.settings().angle {
+            AngleMode::Radians => None,
+            AngleMode::Degrees => Some(divide(variable("pi"), number(180))),
+            AngleMode::Gradians => Some(divide(variable("pi"), number(200))),
+        }
+    }
+
+    // d/du of the builtin `name` at u, before the chain rule multiplies in du/dx
+    fn outer_derivative(&self, name: &str, u: &ASTNode, span: Span) -> Result<ASTNode, CalcError> {
+        let u = || u.clone();
+        let to_radians = |derivative: ASTNode| match self.angle_factor() {
+            Some(factor) => multiply(derivative, factor),
+            None => derivative,
+        };
+        let from_radians = |derivative: ASTNode| match self.angle_factor() {
+            Some(factor) => divide(derivative, factor),
+            None => derivative,
+        };
+        Ok(match name {
+            "sin" => to_radians(call("cos", u())),
+            "cos" => to_radians(negate(call("sin", u()))),
+            "tan" => to_radians(divide(number(1), power(call("cos", u()), number(2)))),
+            "asin" => from_radians(divide(number(1), call("sqrt", subtract(number(1), power(u(), number(2)))))),
+            "acos" => from_radians(negate(divide(number(1), call("sqrt", subtract(number(1), power(u(), number(2))))))),
+            "atan" => from_radians(divide(number(1), add(number(1), power(u(), number(2))))),
+            "sinh" => call("cosh", u()),
+            "cosh" => call("sinh", u()),
+            "tanh" => divide(number(1), power(call("cosh", u()), number(2))),
+            "exp" => call("exp", u()),
+            "ln" => divide(number(1), u()),
+            "log10" => divide(number(1), multiply(u(), call("ln", number(10)))),
+            "log2" => divide(number(1), multiply(u(), call("ln", number(2)))),
+            "sqrt" => divide(number(1), multiply(number(2), call("sqrt", u()))),
+            "cbrt" => divide(number(1), multiply(number(3), power(call("cbrt", u()), number(2)))),
+            "abs" => call("sign", u()),
+            name => return Err(CalcError::type_error(format!("cannot differentiate `{}`", name), span)),
+        })
+    }
+
+    fn differentiate(&mut self, node: &ASTNode) -> Result<ASTNode, CalcError> {
+        if !depends_on(node, self.variable) && !matches!(node.kind, ASTNodeKind::Function(..)) {
+            return Ok(number(0));
+        }
+        let span: Span = node.span;
+        Ok(match &node.kind {
+            ASTNodeKind::Variable(_) | ASTNodeKind::Unit(_) => number(1),
+            ASTNodeKind::Unary(Operator::Subtract, operand) => negate(self.differentiate(operand)?),
+            ASTNodeKind::Unary(Operator::Add, operand) => self.differentiate(operand)?,
+            ASTNodeKind::Binary(Operator::Add, left, right) => add(self.differentiate(left)?, self.differentiate(right)?),
+            ASTNodeKind::Binary(Operator::Subtract, left, right) => subtract(self.differentiate(left)?, self.differentiate(right)?),
+            // Product rule
+            ASTNodeKind::Binary(Operator::Multiply, left, right) => {
+                add(multiply(self.differentiate(left)?, (**right).clone()), multiply((**left).clone(), self.differentiate(right)?))
+            }
+            // Quotient rule
+            ASTNodeKind::Binary(Operator::Divide, left, right) => divide(
+                subtract(multiply(self.differentiate(left)?, (**right).clone()), multiply((**left).clone(), self.differentiate(right)?)),
+                power((**right).clone(), number(2)),
+            ),
+            ASTNodeKind::Binary(Operator::Exponent, base, exponent) => {
+                let (base, exponent) = ((**base).clone(), (**exponent).clone());
+                if !depends_on(&exponent, self.variable) {
+                    // Power rule
+                    multiply(multiply(exponent.clone(), power(base.clone(), subtract(exponent, number(1)))), self.differentiate(&base)?)
+                } else {
+                    // d(u^v) = u^v * (v' ln(u) + v u' / u)
+                    let log_term: ASTNode = multiply(self.differentiate(&exponent)?, call("ln", base.clone()));
+                    let base_term: ASTNode = divide(multiply(exponent.clone(), self.differentiate(&base)?), base.clone());
+                    multiply(power(base, exponent), add(log_term, base_term))
+                }
+            }
+            ASTNodeKind::Function(name, args) => return self.differentiate_call(name, args, span),
+            _ => return Err(CalcError::type_error(format!("cannot differentiate `{}`", node), span)),
+        })
+    }
+
+    // Chain rule for builtins, user functions are expanded into their definition
+    fn differentiate_call(&mut self, name: &str, args: &[ASTNode], span: Span) -> Result<ASTNode, CalcError> {
+        if let Some(function) = self.env.function(name) {
+            if function.params.len() != args.len() {
+                return Err(CalcError::arity(format!("{} expects {} argument(s), got {}", name, function.params.len(), args.len()), span));
+            }
+            if self.depth >= MAX_CALL_DEPTH {
+                return Err(CalcError::overflow(format!("maximum call depth of {} exceeded in `{}`", MAX_CALL_DEPTH, name), span));
+            }
+            let bindings: Vec<(String, ASTNode)> = function.params.iter().cloned().zip(args.iter().cloned()).collect();
+            let body: ASTNode = substitute(&function.body, &bindings);
+            self.depth += 1;
+            let result = self.differentiate(&body);
+            self.depth -= 1;
+            return result;
+        }
+        if !args.iter().any(|arg| depends_on(arg, self.variable)) {
+            return Ok(number(0));
+        }
+        match args {
+            [u] => Ok(multiply(self.outer_derivative(name, u, span)?, self.differentiate(u)?)),
+            // log(base, x) with a constant base
+            [base, x] if name == "log" && !depends_on(base, self.variable) => {
+                Ok(divide(self.differentiate(x)?, multiply(x.clone(), call("ln", base.clone()))))
+            }
+            _ => Err(CalcError::type_error(format!("cannot differentiate `{}` with respect to `{}`", name, self.variable), span)),
+        }
+    }
+}
+
+/// Derivative of `node` with respect to `variable`, simplified. Calls of
+/// user functions are expanded into their definitions first.
+pub fn differentiate(node: &ASTNode, variable: &str, env: &Environment) -> Result<ASTNode, CalcError> {
+    let mut differentiator: Differentiator = Differentiator { variable, env, depth: 0 };
+    Ok(simplify(differentiator.differentiate(node)?))
+}
+
+/// Evaluates `diff(expr, var)` or `simplify(expr)`. Results that still
+/// contain variables come back as expressions, constant ones as numbers.
+pub fn evaluate_form(name: &str, arg_nodes: Vec<ASTNode>, span: Span, env: &mut Environment) -> Result<Value, CalcError> {
+    let expected: usize = if name == "diff" { 2 } else { 1 };
+    if arg_nodes.len() != expected {
+        return Err(CalcError::arity(format!("{} expects {} argument{}, got {}", name, expected, if expected == 1 { "" } else { "s" }, arg_nodes.len()), span));
+    }
+    let result: ASTNode = match arg_nodes.as_slice() {
+        [expression, variable] => match &variable.kind {
+            ASTNodeKind::Variable(variable) => differentiate(expression, variable, env)?,
+            _ => return Err(CalcError::parse("diff expects a variable name as its second argument", variable.span)),
+        },
+        [expression] => simplify(expression.clone()),
+        _ => unreachable!(),
+    };
+    match constant(&result) {
+        Some(value) => Ok(value),
+        None => Ok(Value::Expression(Box::new(result))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{engine::Engine, error::ErrorKind, testing::*};
+
+    #[test]
+    fn differentiates() {
+        assert_eq!(eval("diff(x^2*sin(x), x)"), "2*x*sin(x) + x^2*cos(x)");
+        assert_eq!(eval("diff(3*x^3 + 2*x - 7, x)"), "9*x^2 + 2");
+        assert_eq!(eval("diff(sqrt(x), x)"), "1/(2*sqrt(x))");
+        assert_eq!(eval("diff(ln(x)/x, x)"), "(-ln(x) + 1)/x^2");
+        assert_eq!(eval("diff(x^x, x)"), "x^x*(ln(x) + 1)");
+        assert_eq!(eval("diff(x^2*y, y)"), "x^2");
+        assert_eq!(eval("diff(5, x)"), "0");
+    }
+
+    #[test]
+    fn differentiates_user_functions() {
+        let mut engine: Engine = Engine::new();
+        engine.eval("f(t) = t^2 + 1").unwrap();
+        assert_eq!(engine.eval("diff(f(x), x)").unwrap().to_string(), "2*x");
+    }
+
+    #[test]
+    fn trig_in_degrees() {
+        let mut engine: Engine = Engine::new();
+        engine.set_angle_mode(crate::AngleMode::Degrees);
+        assert_eq!(engine.eval("diff(sin(x), x)").unwrap().to_string(), "pi*cos(x)/180");
+    }
+
+    #[test]
+    fn simplifies() {
+        assert_eq!(eval("simplify(x*1 + 0 + x + 2*x)"), "4*x");
+        assert_eq!(eval("simplify(x*y + y*x)"), "2*x*y");
+        assert_eq!(eval("simplify(x*x*x/x)"), "x^2");
+        assert_eq!(eval("simplify(x - 3*x)"), "-2*x");
+        assert_eq!(eval("simplify((x^2)^3)"), "x^6");
+        assert_eq!(eval("simplify((x^2)^(1/2))"), "(x^2)^(1/2)");
+        assert_eq!(eval("diff(sin(x)^2 + cos(x)^2, x)"), "0");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error_kind("diff(floor(x), x)"), ErrorKind::Type);
+        assert_eq!(error_kind("diff(x^2, 3)"), ErrorKind::Parse);
+        assert_eq!(error_kind("diff(x^2)"), ErrorKind::Arity);
+    }
+}
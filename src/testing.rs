@@ -0,0 +1,19 @@
+// Helpers shared by the unit tests, each evaluating on a fresh engine
+use crate::{engine::Engine, error::ErrorKind, numeric};
+
+pub fn eval(input: &str) -> String {
+    Engine::new().eval(input).unwrap().to_string()
+}
+
+pub fn eval_float(input: &str) -> f64 {
+    numeric::to_float(&Engine::new().eval(input).unwrap()).unwrap()
+}
+
+pub fn error_kind(input: &str) -> ErrorKind {
+    Engine::new().eval(input).unwrap_err().kind
+}
+
+// What the REPL prints for `input`, honouring any `to <format>` suffix
+pub fn render(input: &str) -> Result<String, ErrorKind> {
+    Engine::new().eval_to_string(input).map_err(|error| error.kind)
+}
@@ -0,0 +1,376 @@
+use std::{cmp::Ordering, fmt};
+
+use crate::{builtins::Builtin, error::CalcError, functions::CallContext, interpreter::Value, lexer::*, numeric};
+
+// Powers of metre, kilogram, second, ampere, kelvin, mole, candela and bit
+pub type Dimension = [i32; 8];
+
+const BASE_UNITS: [&str; 8] = ["m", "kg", "s", "A", "K", "mol", "cd", "bit"];
+
+const NONE: Dimension = [0, 0, 0, 0, 0, 0, 0, 0];
+const LENGTH: Dimension = [1, 0, 0, 0, 0, 0, 0, 0];
+const MASS: Dimension = [0, 1, 0, 0, 0, 0, 0, 0];
+const TIME: Dimension = [0, 0, 1, 0, 0, 0, 0, 0];
+const CURRENT: Dimension = [0, 0, 0, 1, 0, 0, 0, 0];
+const TEMPERATURE: Dimension = [0, 0, 0, 0, 1, 0, 0, 0];
+const AMOUNT: Dimension = [0, 0, 0, 0, 0, 1, 0, 0];
+const LUMINOSITY: Dimension = [0, 0, 0, 0, 0, 0, 1, 0];
+const INFORMATION: Dimension = [0, 0, 0, 0, 0, 0, 0, 1];
+const AREA: Dimension = [2, 0, 0, 0, 0, 0, 0, 0];
+const VOLUME: Dimension = [3, 0, 0, 0, 0, 0, 0, 0];
+const SPEED: Dimension = [1, 0, -1, 0, 0, 0, 0, 0];
+const FREQUENCY: Dimension = [0, 0, -1, 0, 0, 0, 0, 0];
+const FORCE: Dimension = [1, 1, -2, 0, 0, 0, 0, 0];
+const ENERGY: Dimension = [2, 1, -2, 0, 0, 0, 0, 0];
+const POWER: Dimension = [2, 1, -3, 0, 0, 0, 0, 0];
+const PRESSURE: Dimension = [-1, 1, -2, 0, 0, 0, 0, 0];
+const CHARGE: Dimension = [0, 0, 1, 1, 0, 0, 0, 0];
+const VOLTAGE: Dimension = [2, 1, -3, -1, 0, 0, 0, 0];
+const RESISTANCE: Dimension = [2, 1, -3, -2, 0, 0, 0, 0];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Prefixes {
+    None,
+    Si,
+    // Data sizes also take KiB, MiB etc.
+    SiAndBinary,
+}
+
+const SI_PREFIXES: &[(&str, f64)] = &[
+    ("n", 1e-9), ("u", 1e-6), ("m", 1e-3), ("c", 1e-2), ("k", 1e3), ("M", 1e6), ("G", 1e9), ("T", 1e12), ("P", 1e15),
+];
+
+const BINARY_PREFIXES: &[(&str, f64)] = &[
+    ("Ki", 1024f64), ("Mi", 1048576f64), ("Gi", 1073741824f64), ("Ti", 1099511627776f64), ("Pi", 1125899906842624f64),
+];
+
+// Name, size in SI base units, dimension and which prefixes it takes
+const UNITS: &[(&str, f64, Dimension, Prefixes)] = &[
+    ("m", 1f64, LENGTH, Prefixes::Si),
+    ("inch", 0.0254, LENGTH, Prefixes::None),
+    ("ft", 0.3048, LENGTH, Prefixes::None),
+    ("yd", 0.9144, LENGTH, Prefixes::None),
+    ("mi", 1609.344, LENGTH, Prefixes::None),
+    ("nmi", 1852f64, LENGTH, Prefixes::None),
+    ("g", 1e-3, MASS, Prefixes::Si),
+    ("t", 1e3, MASS, Prefixes::None),
+    ("oz", 0.028349523125, MASS, Prefixes::None),
+    ("lb", 0.45359237, MASS, Prefixes::None),
+    ("st", 6.35029318, MASS, Prefixes::None),
+    ("s", 1f64, TIME, Prefixes::Si),
+    ("min", 60f64, TIME, Prefixes::None),
+    ("h", 3600f64, TIME, Prefixes::None),
+    ("day", 86400f64, TIME, Prefixes::None),
+    ("week", 604800f64, TIME, Prefixes::None),
+    ("yr", 31557600f64, TIME, Prefixes::None),
+    ("A", 1f64, CURRENT, Prefixes::Si),
+    ("K", 1f64, TEMPERATURE, Prefixes::Si),
+    ("mol", 1f64, AMOUNT, Prefixes::Si),
+    ("cd", 1f64, LUMINOSITY, Prefixes::None),
+    ("bit", 1f64, INFORMATION, Prefixes::SiAndBinary),
+    ("B", 8f64, INFORMATION, Prefixes::SiAndBinary),
+    ("ha", 1e4, AREA, Prefixes::None),
+    ("acre", 4046.8564224, AREA, Prefixes::None),
+    ("L", 1e-3, VOLUME, Prefixes::Si),
+    ("gal", 3.785411784e-3, VOLUME, Prefixes::None),
+    ("qt", 9.46352946e-4, VOLUME, Prefixes::None),
+    ("pt", 4.73176473e-4, VOLUME, Prefixes::None),
+    ("floz", 2.95735295625e-5, VOLUME, Prefixes::None),
+    ("mph", 0.44704, SPEED, Prefixes::None),
+    ("knot", 1852f64 / 3600f64, SPEED, Prefixes::None),
+    ("Hz", 1f64, FREQUENCY, Prefixes::Si),
+    ("N", 1f64, FORCE, Prefixes::Si),
+    ("lbf", 4.4482216152605, FORCE, Prefixes::None),
+    ("J", 1f64, ENERGY, Prefixes::Si),
+    ("Wh", 3600f64, ENERGY, Prefixes::Si),
+    ("cal", 4.184, ENERGY, Prefixes::Si),
+    ("eV", 1.602176634e-19, ENERGY, Prefixes::Si),
+    ("BTU", 1055.05585262, ENERGY, Prefixes::None),
+    ("W", 1f64, POWER, Prefixes::Si),
+    ("hp", 745.6998715822702, POWER, Prefixes::None),
+    ("Pa", 1f64, PRESSURE, Prefixes::Si),
+    ("bar", 1e5, PRESSURE, Prefixes::Si),
+    ("atm", 101325f64, PRESSURE, Prefixes::None),
+    ("psi", 6894.757293168, PRESSURE, Prefixes::None),
+    ("C", 1f64, CHARGE, Prefixes::Si),
+    ("V", 1f64, VOLTAGE, Prefixes::Si),
+    ("ohm", 1f64, RESISTANCE, Prefixes::Si),
+];
+
+/// Size in SI base units and dimension of a unit name like `km` or `MiB`.
+pub fn lookup(name: &str) -> Option<(f64, Dimension)> {
+    if let Some((_, scale, dimension, _)) = UNITS.iter().find(|(unit, ..)| *unit == name) {
+        return Some((*scale, *dimension));
+    }
+    let prefixed = |prefixes: &[(&str, f64)], allowed: &dyn Fn(Prefixes) -> bool| {
+        prefixes.iter().find_map(|(prefix, factor)| {
+            let (_, scale, dimension, _) = UNITS.iter().find(|(unit, .., kind)| allowed(*kind) && name.strip_prefix(prefix) == Some(unit))?;
+            Some((factor * scale, *dimension))
+        })
+    };
+    prefixed(SI_PREFIXES, &|kind| kind != Prefixes::None)
+        .or_else(|| prefixed(BINARY_PREFIXES, &|kind| kind == Prefixes::SiAndBinary))
+}
+
+pub fn is_unit(name: &str) -> bool {
+    lookup(name).is_some()
+}
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    UNITS.iter().map(|(name, ..)| *name)
+}
+
+/// A product of named units raised to integer powers, e.g. `m/s^2`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Unit {
+    factors: Vec<(String, i32)>,
+}
+
+impl Unit {
+    /// Unit with a single factor, the name must be known to [`lookup`].
+    pub fn named(name: &str) -> Unit {
+        Unit { factors: vec![(name.to_string(), 1)] }
+    }
+
+    pub fn push(&mut self, name: &str, power: i32) {
+        match self.factors.iter_mut().find(|(factor, _)| factor == name) {
+            Some((_, existing)) => *existing += power,
+            None => self.factors.push((name.to_string(), power)),
+        }
+        self.factors.retain(|(_, power)| *power != 0);
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.factors.iter().map(|(name, power)| lookup(name).unwrap().0.powi(*power)).product()
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        let mut dimension: Dimension = NONE;
+        for (name, power) in self.factors.iter() {
+            let (_, base) = lookup(name).unwrap();
+            for (total, base) in dimension.iter_mut().zip(base) {
+                *total += base * power;
+            }
+        }
+        dimension
+    }
+
+    fn multiply(&self, other: &Unit, sign: i32) -> Unit {
+        let mut unit: Unit = self.clone();
+        for (name, power) in other.factors.iter() {
+            unit.push(name, power * sign);
+        }
+        unit
+    }
+
+    fn power(&self, exponent: i32) -> Unit {
+        Unit { factors: self.factors.iter().map(|(name, power)| (name.clone(), power * exponent)).filter(|(_, power)| *power != 0).collect() }
+    }
+}
+
+// `kg*m/s^2`, or `s^-1` when nothing is left above the line
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let factor = |(name, power): &(String, i32)| if *power == 1 { name.clone() } else { format!("{}^{}", name, power) };
+        let numerator: Vec<String> = self.factors.iter().filter(|(_, power)| *power > 0).map(factor).collect();
+        let denominator: Vec<String> = self.factors.iter().filter(|(_, power)| *power < 0).map(|(name, power)| factor(&(name.clone(), -power))).collect();
+        match (numerator.is_empty(), denominator.is_empty()) {
+            (true, _) => write!(f, "{}", self.factors.iter().map(factor).collect::<Vec<String>>().join("*")),
+            (false, true) => write!(f, "{}", numerator.join("*")),
+            (false, false) => write!(f, "{}/{}", numerator.join("*"), denominator.join("/")),
+        }
+    }
+}
+
+fn dimension_name(dimension: Dimension) -> String {
+    let mut unit: Unit = Unit::default();
+    for (name, power) in BASE_UNITS.iter().zip(dimension) {
+        unit.push(name, power);
+    }
+    if unit.factors.is_empty() { String::from("a plain number") } else { format!("`{}`", unit) }
+}
+
+/// A number with a unit attached, e.g. `5 km`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: Unit,
+}
+
+impl Quantity {
+    // Units that cancel out, like `km/m`, leave a plain number behind
+    fn normalize(self) -> Value {
+        if self.unit.dimension() == NONE {
+            Value::Number(self.value * self.unit.scale())
+        } else {
+            Value::Quantity(self)
+        }
+    }
+
+    /// The same amount expressed in `unit`, `None` if the dimensions differ.
+    pub fn convert(&self, unit: &Unit) -> Option<Quantity> {
+        if self.unit.dimension() != unit.dimension() {
+            return None;
+        }
+        Some(Quantity { value: self.value * self.unit.scale() / unit.scale(), unit: unit.clone() })
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Unit scales like 1e-2^3 aren't exact in binary, hide the noise they leave in the last digits
+        let value: f64 = format!("{:.14e}", self.value).parse::<f64>().unwrap_or(self.value);
+        write!(f, "{} {}", value, self.unit)
+    }
+}
+
+fn to_quantity(value: &Value, span: Span) -> Result<Quantity, CalcError> {
+    match value {
+        Value::Quantity(quantity) => Ok(quantity.clone()),
+        Value::Complex(_) => Err(CalcError::type_error("complex numbers cannot have units", span)),
+        value => match numeric::to_float(value) {
+            Some(value) => Ok(Quantity { value, unit: Unit::default() }),
+            None => Err(CalcError::type_error("Attempt to perform arithmetic/bitwise operators on non-numbers", span)),
+        },
+    }
+}
+
+fn incompatible(action: &str, a: &Quantity, b: &Quantity, span: Span) -> CalcError {
+    CalcError::type_error(format!("cannot {} {} and {}", action, dimension_name(a.unit.dimension()), dimension_name(b.unit.dimension())), span)
+}
+
+pub fn convert(value: &Value, unit: &Unit, span: Span) -> Result<Value, CalcError> {
+    let quantity: Quantity = to_quantity(value, span)?;
+    match quantity.convert(unit) {
+        Some(converted) => Ok(Value::Quantity(converted)),
+        None => Err(CalcError::type_error(format!("cannot convert {} to `{}`", dimension_name(quantity.unit.dimension()), unit), span)),
+    }
+}
+
+/// Binary operators where at least one side has a unit.
+pub fn operation(operator: &Operator, left: &Value, right: &Value, span: Span) -> Result<Value, CalcError> {
+    let (a, b) = (to_quantity(left, span)?, to_quantity(right, span)?);
+    match operator {
+        Operator::Add | Operator::Subtract | Operator::Modulo => {
+            // The result is in the unit of the left-hand side
+            let b: Quantity = match b.convert(&a.unit) {
+                Some(b) => b,
+                None => return Err(incompatible(if let Operator::Modulo = operator { "take the remainder of" } else { "add" }, &a, &b, span)),
+            };
+            let value: f64 = numeric::float_operation(operator, a.value, b.value, span)?;
+            Ok(Quantity { value, unit: a.unit }.normalize())
+        }
+        Operator::Multiply => Ok(Quantity { value: a.value * b.value, unit: a.unit.multiply(&b.unit, 1) }.normalize()),
+        Operator::Divide => Ok(Quantity { value: a.value / b.value, unit: a.unit.multiply(&b.unit, -1) }.normalize()),
+        Operator::Exponent => {
+            if b.unit.dimension() != NONE {
+                return Err(CalcError::type_error(format!("exponent must be a plain number, got {}", dimension_name(b.unit.dimension())), span));
+            }
+            let exponent: f64 = b.value * b.unit.scale();
+            if exponent.fract() != 0f64 || exponent.abs() > i32::MAX as f64 {
+                return Err(CalcError::type_error("units can only be raised to integer powers", span));
+            }
+            Ok(Quantity { value: a.value.powi(exponent as i32), unit: a.unit.power(exponent as i32) }.normalize())
+        }
+        _ => Err(CalcError::type_error(format!("`{}` is not defined for quantities with units", operator), span)),
+    }
+}
+
+pub fn negate(quantity: Quantity) -> Value {
+    Value::Quantity(Quantity { value: -quantity.value, ..quantity })
+}
+
+// Only quantities of the same dimension compare, in whatever units they're written
+pub fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    let (a, b) = (to_quantity(left, Span::default()).ok()?, to_quantity(right, Span::default()).ok()?);
+    let b: Quantity = b.convert(&a.unit)?;
+    // Same noise allowance as Display, so `1 L == 1000 cm^3`
+    if (a.value - b.value).abs() <= 1e-14 * a.value.abs().max(b.value.abs()) {
+        return Some(Ordering::Equal);
+    }
+    a.value.partial_cmp(&b.value)
+}
+
+/// Calls `builtin` with at least one quantity argument. Functions that only
+/// rescale their input keep the unit, everything else needs plain numbers.
+pub fn call_builtin(builtin: &Builtin, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+    let span: Span = context.span;
+    let first: Quantity = to_quantity(&args[0], span)?;
+    match builtin.name {
+        "abs" | "floor" | "ceil" | "round" | "trunc" => {
+            let mut floats: Vec<f64> = vec![first.value];
+            for arg in args[1..].iter() {
+                floats.push(numeric::to_float(arg).ok_or_else(|| CalcError::type_error(format!("{}() digits must be a plain number", builtin.name), span))?);
+            }
+            Ok(Value::Quantity(Quantity { value: builtin.call_float(&floats, span)?, unit: first.unit }))
+        }
+        "sqrt" => {
+            let mut unit: Unit = Unit::default();
+            for (name, power) in first.unit.factors.iter() {
+                if power % 2 != 0 {
+                    return Err(CalcError::type_error(format!("cannot take the square root of `{}`", first.unit), span));
+                }
+                unit.push(name, power / 2);
+            }
+            Ok(Value::Quantity(Quantity { value: first.value.sqrt(), unit }))
+        }
+        name => Err(CalcError::type_error(format!("`{}` is not defined for quantities with units", name), span)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::Engine, error::ErrorKind, format::Format, testing::*};
+
+    #[test]
+    fn converts_between_units() {
+        assert_eq!(eval("100 cm to m"), "1 m");
+        assert_eq!(eval("1 mi to km"), "1.609344 km");
+        assert_eq!(eval("72 km/h in m/s"), "20 m/s");
+        assert_eq!(eval("1 h to s"), "3600 s");
+        assert_eq!(eval("1 KiB to B"), "1024 B");
+    }
+
+    #[test]
+    fn arithmetic_keeps_units() {
+        assert_eq!(eval("5 km + 300 m"), "5.3 km");
+        assert_eq!(eval("9.81 m/s^2 * 70 kg"), "686.7 m*kg/s^2");
+    }
+
+    #[test]
+    fn mismatched_dimensions_are_errors() {
+        assert_eq!(error_kind("1 m + 1 s"), ErrorKind::Type);
+        assert_eq!(error_kind("1 m to s"), ErrorKind::Type);
+        let metre: Quantity = Quantity { value: 1f64, unit: Unit::named("m") };
+        assert!(metre.convert(&Unit::named("kg")).is_none());
+    }
+
+    #[test]
+    fn conversions_keep_full_precision() {
+        let third: Quantity = Quantity { value: 1f64 / 3f64, unit: Unit::named("m") };
+        let converted: Quantity = third.convert(&Unit::named("cm")).unwrap();
+        assert!((converted.value - 100f64 / 3f64).abs() < 1e-13);
+        assert_eq!(render("100 cm to m"), Ok(String::from("1 m")));
+        assert_eq!(eval("1 L == 1000 cm^3"), "true");
+        let mut engine: Engine = Engine::new();
+        engine.set_format(Format::Fixed(16));
+        assert_eq!(engine.eval_to_string("(1/3) m to cm").unwrap(), "33.3333333333333286 cm");
+    }
+
+    #[test]
+    fn names_are_units_only_after_a_quantity() {
+        assert_eq!(eval("5 h to s"), "18000 s");
+        assert_eq!(eval("3 m / 2 s"), "1.5 m/s");
+        assert_eq!(eval("2 min(3, 4)"), "6");
+        assert_eq!(error_kind("h"), ErrorKind::UnknownIdentifier);
+        let mut engine: Engine = Engine::new();
+        for assignment in ["t = 5", "m = 5", "s = 1"] {
+            assert!(engine.eval(assignment).is_ok(), "{}", assignment);
+        }
+        assert_eq!(engine.eval("2 m + t").unwrap().to_string(), "15");
+        assert_eq!(engine.eval("1 km to m").unwrap().to_string(), "1000 m");
+        engine.eval("f(g) = 3g^2").unwrap();
+        assert_eq!(engine.eval("f(2)").unwrap().to_string(), "12");
+    }
+}
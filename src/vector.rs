@@ -0,0 +1,369 @@
+use std::cmp::Ordering;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use crate::{builtins::Builtin, complex, environment::Settings, error::CalcError, functions::{CallContext, Function}, interpreter::Value, lexer::*, numeric, rational::MAX_BITS};
+
+type Matrix = Vec<Vec<Value>>;
+
+// Rows of a matrix, a list of equally long lists of numbers
+fn rows(items: &[Value]) -> Option<Vec<&[Value]>> {
+    let rows: Vec<&[Value]> = items.iter().map(|item| match item {
+        Value::List(row) if !row.iter().any(|value| matches!(value, Value::List(_))) => Some(row.as_slice()),
+        _ => None,
+    }).collect::<Option<Vec<&[Value]>>>()?;
+    match rows.first() {
+        Some(first) if !first.is_empty() && rows.iter().all(|row| row.len() == first.len()) => Some(rows),
+        _ => None,
+    }
+}
+
+fn is_matrix(value: &Value) -> bool {
+    matches!(value, Value::List(items) if rows(items).is_some())
+}
+
+fn matrix(value: &Value, name: &str, span: Span) -> Result<Matrix, CalcError> {
+    match value {
+        Value::List(items) => match rows(items) {
+            Some(rows) => Ok(rows.into_iter().map(|row| row.to_vec()).collect()),
+            None => Err(CalcError::type_error(format!("{} expects a matrix, a list of equally long rows", name), span)),
+        },
+        _ => Err(CalcError::type_error(format!("{} expects a matrix, got {}", name, value), span)),
+    }
+}
+
+fn square_matrix(value: &Value, name: &str, span: Span) -> Result<Matrix, CalcError> {
+    let matrix: Matrix = matrix(value, name, span)?;
+    if matrix.len() != matrix[0].len() {
+        return Err(CalcError::type_error(format!("{} expects a square matrix, got {}x{}", name, matrix.len(), matrix[0].len()), span));
+    }
+    Ok(matrix)
+}
+
+fn to_value(matrix: Matrix) -> Value {
+    Value::List(matrix.into_iter().map(Value::List).collect())
+}
+
+fn zero() -> Value {
+    Value::Integer(BigInt::from(0))
+}
+
+fn one() -> Value {
+    Value::Integer(BigInt::from(1))
+}
+
+fn is_zero(value: &Value) -> bool {
+    numeric::compare(value, &zero()) == Some(Ordering::Equal)
+}
+
+// Used to pick the largest pivot, which keeps elimination on floats accurate
+fn magnitude(value: &Value) -> f64 {
+    complex::to_complex(value).map_or(0f64, |value| value.norm())
+}
+
+struct Arithmetic<'a> {
+    span: Span,
+    settings: &'a Settings,
+}
+
+impl Arithmetic<'_> {
+    fn apply(&self, operator: Operator, a: &Value, b: &Value) -> Result<Value, CalcError> {
+        numeric::binary_operation(&operator, a.clone(), b.clone(), self.span, self.settings)
+    }
+
+    fn dot(&self, a: &[Value], b: &[Value]) -> Result<Value, CalcError> {
+        let mut total: Value = zero();
+        for (x, y) in a.iter().zip(b) {
+            total = self.apply(Operator::Add, &total, &self.apply(Operator::Multiply, x, y)?)?;
+        }
+        Ok(total)
+    }
+
+    fn multiply(&self, a: &[Vec<Value>], b: &[Vec<Value>]) -> Result<Matrix, CalcError> {
+        if a[0].len() != b.len() {
+            return Err(CalcError::type_error(format!("cannot multiply a {}x{} matrix by a {}x{} matrix", a.len(), a[0].len(), b.len(), b[0].len()), self.span));
+        }
+        let columns: Matrix = transpose(b);
+        a.iter().map(|row| columns.iter().map(|column| self.dot(row, column)).collect()).collect()
+    }
+
+    // Gaussian elimination, returning the determinant and, when it isn't zero, the inverse
+    fn eliminate(&self, mut matrix: Matrix) -> Result<(Value, Option<Matrix>), CalcError> {
+        let size: usize = matrix.len();
+        let mut inverse: Matrix = identity(size);
+        let mut determinant: Value = one();
+        for column in 0..size {
+            let pivot: usize = (column..size)
+                .max_by(|a, b| magnitude(&matrix[*a][column]).total_cmp(&magnitude(&matrix[*b][column])))
+                .unwrap();
+            if is_zero(&matrix[pivot][column]) {
+                return Ok((zero(), None));
+            }
+            if pivot != column {
+                matrix.swap(pivot, column);
+                inverse.swap(pivot, column);
+                determinant = numeric::negate(determinant, self.span)?;
+            }
+            let pivot_value: Value = matrix[column][column].clone();
+            determinant = self.apply(Operator::Multiply, &determinant, &pivot_value)?;
+            for j in 0..size {
+                matrix[column][j] = self.apply(Operator::Divide, &matrix[column][j], &pivot_value)?;
+                inverse[column][j] = self.apply(Operator::Divide, &inverse[column][j], &pivot_value)?;
+            }
+            for row in 0..size {
+                if row == column || is_zero(&matrix[row][column]) {
+                    continue;
+                }
+                let factor: Value = matrix[row][column].clone();
+                for j in 0..size {
+                    matrix[row][j] = self.apply(Operator::Subtract, &matrix[row][j], &self.apply(Operator::Multiply, &factor, &matrix[column][j])?)?;
+                    inverse[row][j] = self.apply(Operator::Subtract, &inverse[row][j], &self.apply(Operator::Multiply, &factor, &inverse[column][j])?)?;
+                }
+            }
+        }
+        Ok((determinant, Some(inverse)))
+    }
+
+    fn inverse(&self, matrix: Matrix) -> Result<Matrix, CalcError> {
+        match self.eliminate(matrix)? {
+            (_, Some(inverse)) => Ok(inverse),
+            (_, None) => Err(CalcError::domain("matrix is singular and has no inverse", self.span)),
+        }
+    }
+
+    fn power(&self, matrix: Matrix, exponent: &Value) -> Result<Matrix, CalcError> {
+        let exponent: i64 = match exponent {
+            Value::Integer(exponent) => exponent.to_i64(),
+            _ => None,
+        }.ok_or_else(|| CalcError::type_error(format!("matrices can only be raised to integer powers, got {}", exponent), self.span))?;
+        let mut base: Matrix = if exponent < 0 { self.inverse(matrix)? } else { matrix };
+        let mut result: Matrix = identity(base.len());
+        let mut exponent: u64 = exponent.unsigned_abs();
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                self.check_size(&result, &base)?;
+                result = self.multiply(&result, &base)?;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                self.check_size(&base, &base)?;
+                base = self.multiply(&base, &base)?;
+            }
+        }
+        Ok(result)
+    }
+
+    // Entries of a product of exact matrices have about as many bits as the two factors' entries together
+    fn check_size(&self, a: &[Vec<Value>], b: &[Vec<Value>]) -> Result<(), CalcError> {
+        if largest_bits(a).saturating_add(largest_bits(b)) > MAX_BITS {
+            return Err(CalcError::overflow(format!("exact result of matrix `^` would exceed {} bits", MAX_BITS), self.span));
+        }
+        Ok(())
+    }
+
+    // Product of matching elements, pairing up nested lists too, so matrices multiply entry by entry
+    fn hadamard(&self, a: &[Value], b: &[Value]) -> Result<Value, CalcError> {
+        if a.len() != b.len() {
+            return Err(CalcError::type_error(format!("hadamard expects lists of the same shape, got lengths {} and {}", a.len(), b.len()), self.span));
+        }
+        a.iter().zip(b).map(|(x, y)| match (x, y) {
+            (Value::List(x), Value::List(y)) => self.hadamard(x, y),
+            (Value::List(_), _) | (_, Value::List(_)) => Err(CalcError::type_error("hadamard expects lists of the same shape", self.span)),
+            (x, y) => self.apply(Operator::Multiply, x, y),
+        }).collect::<Result<Vec<Value>, CalcError>>().map(Value::List)
+    }
+
+    fn elementwise(&self, operator: &Operator, a: &[Value], b: &[Value]) -> Result<Value, CalcError> {
+        if a.len() != b.len() {
+            return Err(CalcError::type_error(format!("cannot apply `{}` to lists of length {} and {}", operator, a.len(), b.len()), self.span));
+        }
+        a.iter().zip(b).map(|(x, y)| self.apply(operator.clone(), x, y)).collect::<Result<Vec<Value>, CalcError>>().map(Value::List)
+    }
+}
+
+fn identity(size: usize) -> Matrix {
+    (0..size).map(|row| (0..size).map(|column| if row == column { one() } else { zero() }).collect()).collect()
+}
+
+fn largest_bits(matrix: &[Vec<Value>]) -> u64 {
+    matrix.iter().flatten().map(|value| match value {
+        Value::Integer(value) => value.bits(),
+        Value::Rational(value) => value.numer().bits().max(value.denom().bits()),
+        _ => 0,
+    }).max().unwrap_or(0)
+}
+
+fn transpose(matrix: &[Vec<Value>]) -> Matrix {
+    (0..matrix[0].len()).map(|column| matrix.iter().map(|row| row[column].clone()).collect()).collect()
+}
+
+/// Operators on lists: `*` is the matrix product when either side is a
+/// matrix and `^` a matrix power, everything else applies element by
+/// element, pairing up two lists or spreading a number over one.
+pub fn operation(operator: &Operator, left: &Value, right: &Value, span: Span, settings: &Settings) -> Result<Value, CalcError> {
+    let arithmetic: Arithmetic = Arithmetic { span, settings };
+    match (left, right) {
+        (Value::List(_), Value::List(_)) if matches!(operator, Operator::Multiply) && (is_matrix(left) || is_matrix(right)) => {
+            // A plain list is a row on the left of a matrix and a column on its right
+            let row = |value: &Value| match value {
+                Value::List(items) if !is_matrix(value) => Ok(vec![items.clone()]),
+                value => matrix(value, "`*`", span),
+            };
+            let column = |value: &Value| match value {
+                Value::List(items) if !is_matrix(value) => Ok(items.iter().map(|item| vec![item.clone()]).collect()),
+                value => matrix(value, "`*`", span),
+            };
+            let product: Matrix = arithmetic.multiply(&row(left)?, &column(right)?)?;
+            Ok(match (is_matrix(left), is_matrix(right)) {
+                (false, _) => Value::List(product.into_iter().next().unwrap()),
+                (_, false) => Value::List(product.into_iter().map(|row| row.into_iter().next().unwrap()).collect()),
+                _ => to_value(product),
+            })
+        }
+        (Value::List(a), Value::List(b)) => arithmetic.elementwise(operator, a, b),
+        (Value::List(_), exponent) if matches!(operator, Operator::Exponent) && is_matrix(left) => {
+            Ok(to_value(arithmetic.power(square_matrix(left, "`^`", span)?, exponent)?))
+        }
+        (Value::List(items), scalar) => items.iter().map(|item| arithmetic.apply(operator.clone(), item, scalar)).collect::<Result<Vec<Value>, CalcError>>().map(Value::List),
+        (scalar, Value::List(items)) => items.iter().map(|item| arithmetic.apply(operator.clone(), scalar, item)).collect::<Result<Vec<Value>, CalcError>>().map(Value::List),
+        _ => Err(CalcError::type_error("Attempt to perform arithmetic/bitwise operators on non-numbers", span)),
+    }
+}
+
+pub fn map(items: Vec<Value>, span: Span, function: fn(Value, Span) -> Result<Value, CalcError>) -> Result<Value, CalcError> {
+    items.into_iter().map(|item| function(item, span)).collect::<Result<Vec<Value>, CalcError>>().map(Value::List)
+}
+
+// Lists are equal when their elements are, they have no order
+pub fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::List(a), Value::List(b)) if a.len() == b.len() => {
+            a.iter().zip(b).all(|(x, y)| numeric::compare(x, y) == Some(Ordering::Equal)).then_some(Ordering::Equal)
+        }
+        _ => None,
+    }
+}
+
+pub fn index(value: &Value, index: &Value, span: Span) -> Result<Value, CalcError> {
+    let items: &[Value] = match value {
+        Value::List(items) => items,
+        value => return Err(CalcError::type_error(format!("cannot index into {}, it is not a list", value), span)),
+    };
+    // Negative indices count back from the end
+    let position: Option<usize> = match index {
+        Value::Integer(index) => index.to_i64().and_then(|index| {
+            if index < 0 { items.len().checked_sub(index.unsigned_abs() as usize) } else { Some(index as usize) }
+        }),
+        index => return Err(CalcError::type_error(format!("list index must be an integer, got {}", index), span)),
+    };
+    match position.and_then(|position| items.get(position)) {
+        Some(item) => Ok(item.clone()),
+        None => Err(CalcError::domain(format!("index {} is out of range for a list of length {}", index, items.len()), span)),
+    }
+}
+
+fn list_argument<'a>(value: &'a Value, name: &str, span: Span) -> Result<&'a [Value], CalcError> {
+    match value {
+        Value::List(items) => Ok(items),
+        value => Err(CalcError::type_error(format!("{} expects a list, got {}", name, value), span)),
+    }
+}
+
+// Builtins that work on whole lists rather than on each element
+fn list_builtin(name: &str, args: &[Value], context: &CallContext) -> Result<Option<Value>, CalcError> {
+    let span: Span = context.span;
+    let arithmetic: Arithmetic = Arithmetic { span, settings: context.settings };
+    let result: Value = match name {
+        "len" => Value::Integer(BigInt::from(list_argument(&args[0], name, span)?.len())),
+        "dot" => {
+            let (a, b) = (list_argument(&args[0], name, span)?, list_argument(&args[1], name, span)?);
+            if a.len() != b.len() {
+                return Err(CalcError::type_error(format!("dot expects lists of the same length, got {} and {}", a.len(), b.len()), span));
+            }
+            arithmetic.dot(a, b)?
+        }
+        "cross" => {
+            let (a, b) = (list_argument(&args[0], name, span)?, list_argument(&args[1], name, span)?);
+            if a.len() != 3 || b.len() != 3 {
+                return Err(CalcError::type_error(format!("cross expects two lists of length 3, got {} and {}", a.len(), b.len()), span));
+            }
+            let component = |i: usize, j: usize| -> Result<Value, CalcError> {
+                arithmetic.apply(Operator::Subtract, &arithmetic.apply(Operator::Multiply, &a[i], &b[j])?, &arithmetic.apply(Operator::Multiply, &a[j], &b[i])?)
+            };
+            Value::List(vec![component(1, 2)?, component(2, 0)?, component(0, 1)?])
+        }
+        "hadamard" => arithmetic.hadamard(list_argument(&args[0], name, span)?, list_argument(&args[1], name, span)?)?,
+        "det" => arithmetic.eliminate(square_matrix(&args[0], name, span)?)?.0,
+        "inv" => to_value(arithmetic.inverse(square_matrix(&args[0], name, span)?)?),
+        "transpose" => match &args[0] {
+            // A plain list becomes a column
+            Value::List(items) if !items.iter().any(|item| matches!(item, Value::List(_))) => Value::List(items.iter().map(|item| Value::List(vec![item.clone()])).collect()),
+            value => to_value(transpose(&matrix(value, name, span)?)),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(result))
+}
+
+/// Calls `builtin` with list arguments. Builtins of single numbers apply to
+/// each element, pairing up elements when several arguments are lists.
+pub fn call_builtin(builtin: &Builtin, args: &[Value], context: &CallContext) -> Result<Value, CalcError> {
+    if let Some(result) = list_builtin(builtin.name, args, context)? {
+        return Ok(result);
+    }
+    let lengths: Vec<usize> = args.iter().filter_map(|arg| match arg {
+        Value::List(items) => Some(items.len()),
+        _ => None,
+    }).collect();
+    if lengths.iter().any(|length| *length != lengths[0]) {
+        return Err(CalcError::type_error(format!("{} expects lists of the same length, got {:?}", builtin.name, lengths), context.span));
+    }
+    (0..lengths[0]).map(|i| {
+        let element_args: Vec<Value> = args.iter().map(|arg| match arg {
+            Value::List(items) => items[i].clone(),
+            value => value.clone(),
+        }).collect();
+        builtin.call(&element_args, context)
+    }).collect::<Result<Vec<Value>, CalcError>>().map(Value::List)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::ErrorKind, testing::*};
+
+    #[test]
+    fn determinant_and_inverse() {
+        assert_eq!(eval("det([[1, 2], [3, 4]])"), "-2");
+        assert_eq!(eval("inv([[1, 2], [3, 4]])"), "[[-2, 1], [3/2, -1/2]]");
+        assert_eq!(eval("[[1, 2], [3, 4]] * inv([[1, 2], [3, 4]])"), "[[1, 0], [0, 1]]");
+        assert_eq!(error_kind("inv([[1, 2], [2, 4]])"), ErrorKind::Domain);
+    }
+
+    #[test]
+    fn products_and_powers() {
+        assert_eq!(eval("[[1, 2], [3, 4]] * [5, 6]"), "[17, 39]");
+        assert_eq!(eval("dot([1, 2, 3], [4, 5, 6])"), "32");
+        assert_eq!(eval("cross([1, 0, 0], [0, 1, 0])"), "[0, 0, 1]");
+        assert_eq!(eval("[[1, 1], [1, 0]]^10"), "[[89, 55], [55, 34]]");
+        assert_eq!(error_kind("[[2]]^(2^40)"), ErrorKind::Overflow);
+    }
+
+    #[test]
+    fn elementwise_products() {
+        assert_eq!(eval("hadamard([[1, 2], [3, 4]], [[5, 6], [7, 8]])"), "[[5, 12], [21, 32]]");
+        assert_eq!(eval("hadamard([1, 2, 3], [4, 5, 6])"), "[4, 10, 18]");
+        assert_eq!(eval("[1, 2, 3] * [4, 5, 6]"), "[4, 10, 18]");
+        assert_eq!(error_kind("hadamard([[1, 2], [3, 4]], [[1, 2]])"), ErrorKind::Type);
+        assert_eq!(error_kind("hadamard([[1, 2]], [1, 2])"), ErrorKind::Type);
+        assert_eq!(error_kind("hadamard(1, [1])"), ErrorKind::Type);
+    }
+
+    #[test]
+    fn transpose_and_indexing() {
+        assert_eq!(eval("transpose([[1, 2], [3, 4]])"), "[[1, 3], [2, 4]]");
+        assert_eq!(eval("transpose([1, 2])"), "[[1], [2]]");
+        assert_eq!(error_kind("transpose([[1, 2], [3]])"), ErrorKind::Type);
+        assert_eq!(eval("[10, 20, 30][-1]"), "30");
+    }
+}